@@ -0,0 +1,221 @@
+// (c) 2017 Productize SPRL <joost@productize.be>
+
+// throughput of the parser on a board sized file made of copies of the
+// example footprint; run with `cargo bench --bench parse`
+
+extern crate symbolic_expressions;
+
+use std::fs;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use symbolic_expressions::{lazy, parser, SexpError};
+
+// number of footprints on the board
+const MODULES: usize = 500;
+// how long every case runs
+const TIME: Duration = Duration::from_secs(2);
+
+// the parser before it worked on bytes: it copies the text into a
+// Vec<char> and builds every string a char at a time
+mod old {
+    use symbolic_expressions::{parse_error, Sexp, SexpError};
+
+    #[derive(Default)]
+    struct Parser {
+        data: Vec<char>,
+        position: usize,
+        line: usize,
+        line_position: usize,
+    }
+
+    impl Parser {
+        fn peek(&self) -> Result<char, SexpError> {
+            self.fail_on_eof()?;
+            Ok(self.data[self.position])
+        }
+
+        fn get(&mut self) -> Result<char, SexpError> {
+            self.fail_on_eof()?;
+            let c = self.data[self.position];
+            self.position += 1;
+            self.line_position += 1;
+            if c == '\n' {
+                self.line += 1;
+                self.line_position = 0;
+            }
+            Ok(c)
+        }
+
+        fn inc(&mut self) {
+            let c = self.data[self.position];
+            self.position += 1;
+            self.line_position += 1;
+            if c == '\n' {
+                self.line += 1;
+                self.line_position = 0;
+            }
+        }
+
+        fn eat_space(&mut self) {
+            while !self.eof() {
+                let c = self.data[self.position];
+                if c == ' ' || c == '\t' {
+                    self.inc();
+                    continue;
+                }
+                break;
+            }
+        }
+
+        fn eat_char(&mut self, c: char) -> Result<(), SexpError> {
+            let c2 = self.get()?;
+            if c != c2 {
+                self.parse_error(&format!("expected {} got {}", c, c2))
+            } else {
+                Ok(())
+            }
+        }
+
+        fn eof(&self) -> bool {
+            self.position >= self.data.len()
+        }
+
+        fn fail_on_eof(&self) -> Result<(), SexpError> {
+            if self.eof() {
+                return self.parse_error("End of file reached");
+            }
+            Ok(())
+        }
+
+        fn parse_error<T>(&self, msg: &str) -> Result<T, SexpError> {
+            parse_error(self.line + 1, self.line_position + 1, msg.to_string())
+        }
+    }
+
+    pub fn parse_str(sexp: &str) -> Result<Sexp, SexpError> {
+        if sexp.is_empty() {
+            return Ok(Sexp::default());
+        }
+        let mut parser = Parser {
+            data: sexp.chars().collect(),
+            ..Default::default()
+        };
+        parse(&mut parser)
+    }
+
+    fn parse(parser: &mut Parser) -> Result<Sexp, SexpError> {
+        parser.eat_space();
+        let c = parser.peek()?;
+        if c == '(' {
+            parse_list(parser)
+        } else if c == '"' {
+            parse_quoted_string(parser)
+        } else if c == ')' {
+            parser.parse_error("Unexpected )")
+        } else {
+            parse_bare_string(parser)
+        }
+    }
+
+    fn parse_list(parser: &mut Parser) -> Result<Sexp, SexpError> {
+        parser.eat_char('(')?;
+        let mut v = vec![];
+        while !parser.eof() {
+            let c = parser.peek()?;
+            if c == ')' {
+                break;
+            } else if c == ' ' || c == '\t' || c == '\r' || c == '\n' {
+                parser.inc()
+            } else {
+                let s = parse(parser)?;
+                v.push(s)
+            }
+        }
+        parser.eat_char(')')?;
+        parser.eat_space();
+        Ok(Sexp::List(v))
+    }
+
+    fn parse_quoted_string(parser: &mut Parser) -> Result<Sexp, SexpError> {
+        let mut s = String::new();
+        parser.eat_char('"')?;
+        // note that escaped quotes are actually not allowed
+        let mut escape = false;
+        while !parser.eof() {
+            let c = parser.peek()?;
+            if c == '\\' {
+                escape = true;
+            } else if c == '"' {
+                if !escape {
+                    break;
+                } else {
+                    escape = false;
+                }
+            } else {
+                escape = false;
+            }
+            s.push(c);
+            parser.inc()
+        }
+        parser.eat_char('"')?;
+        Ok(Sexp::String(s))
+    }
+
+    fn parse_bare_string(parser: &mut Parser) -> Result<Sexp, SexpError> {
+        let mut s = String::new();
+        while !parser.eof() {
+            let c = parser.peek()?;
+            if c == ' ' || c == '(' || c == ')' || c == '\r' || c == '\n' {
+                break;
+            }
+            s.push(c);
+            parser.inc()
+        }
+        Ok(Sexp::String(s))
+    }
+}
+
+fn board() -> String {
+    let module = fs::read_to_string("examples/SILABS_EFM32_QFN24.kicad_mod").unwrap();
+    let mut text = String::from("(kicad_pcb (version 4) (host pcbnew 4.0.7)\n");
+    for _ in 0..MODULES {
+        text.push_str(&module);
+    }
+    text.push(')');
+    text
+}
+
+// run `f` for `TIME` and report the input bytes per second of the fastest run
+fn bench<T, F>(name: &str, text: &str, mut f: F)
+where
+    F: FnMut(&str) -> Result<T, SexpError>,
+{
+    let start = Instant::now();
+    let mut best = Duration::MAX;
+    while start.elapsed() < TIME {
+        let run = Instant::now();
+        black_box(f(black_box(text)).unwrap());
+        best = best.min(run.elapsed());
+    }
+    println!(
+        "{:<24} {:>8.1} MB/s {:>10.3} ms/run",
+        name,
+        text.len() as f64 / best.as_secs_f64() / 1e6,
+        best.as_secs_f64() * 1e3
+    );
+}
+
+fn main() {
+    let board = board();
+    println!("board of {} footprints, {} bytes", MODULES, board.len());
+    bench("parse_str", &board, parser::parse_str);
+    bench("old parse_str", &board, old::parse_str);
+    bench("lazy::parse_str", &board, |s| Ok(lazy::parse_str(s)?.len()));
+    // long quoted strings, like descriptions and file paths
+    let description = format!("(layer \"{}\" ", "a long description ".repeat(8));
+    let quoted = board.replace("(layer ", &description);
+    bench("parse_str quoted", &quoted, parser::parse_str);
+    bench("old parse_str quoted", &quoted, old::parse_str);
+    bench("lazy::parse_str quoted", &quoted, |s| Ok(lazy::parse_str(s)?.len()));
+}
@@ -0,0 +1,91 @@
+// (c) 2017 Productize SPRL <joost@productize.be>
+
+// throughput of the serializer on a board sized file made of copies of the
+// example footprint; run with `cargo bench --bench serialize`
+
+extern crate symbolic_expressions;
+
+use std::hint::black_box;
+use std::io;
+use std::time::{Duration, Instant};
+
+use symbolic_expressions::kicad::KicadVersion;
+use symbolic_expressions::{parser, ser, PrettyFormatter, Rules, Sexp, SexpError};
+
+// number of footprints on the board
+const MODULES: usize = 500;
+// how long every case runs
+const TIME: Duration = Duration::from_secs(2);
+
+fn board() -> Sexp {
+    let module = parser::parse_file("examples/SILABS_EFM32_QFN24.kicad_mod").unwrap();
+    let mut list = vec![
+        Sexp::String("kicad_pcb".into()),
+        parser::parse_str("(version 4)").unwrap(),
+        parser::parse_str("(host pcbnew 4.0.7)").unwrap(),
+    ];
+    list.extend((0..MODULES).map(|_| module.clone()));
+    Sexp::List(list)
+}
+
+// run `f` for `TIME` and report the output bytes per second of the fastest run
+fn bench<F>(name: &str, mut f: F)
+where
+    F: FnMut() -> Result<usize, SexpError>,
+{
+    let start = Instant::now();
+    let mut best = Duration::MAX;
+    let mut bytes = 0;
+    while start.elapsed() < TIME {
+        let run = Instant::now();
+        bytes = black_box(f().unwrap());
+        best = best.min(run.elapsed());
+    }
+    println!(
+        "{:<24} {:>8.1} MB/s {:>10.3} ms/run",
+        name,
+        bytes as f64 / best.as_secs_f64() / 1e6,
+        best.as_secs_f64() * 1e3
+    );
+}
+
+// a writer that only counts, so the bench measures the serializer
+struct Count(usize);
+
+impl io::Write for Count {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn main() {
+    let board = board();
+    let size = ser::to_vec(&board).unwrap().len();
+    println!("board of {} footprints, {} bytes", MODULES, size);
+    let mut rules = Rules::new();
+    rules.insert("module", 1);
+    rules.insert("pad", 2);
+    rules.insert("fp_poly", 2);
+    bench("to_vec", || Ok(ser::to_vec(&board)?.len()));
+    bench("to_string", || Ok(ser::to_string(&board)?.len()));
+    bench("to_writer", || {
+        let mut w = Count(0);
+        ser::to_writer(&mut w, &board)?;
+        Ok(w.0)
+    });
+    bench("to_string_with_rules", || Ok(ser::to_string_with_rules(&board, rules.clone())?.len()));
+    bench("to_string_kicad", || Ok(ser::to_string_kicad(&board, KicadVersion::Modern)?.len()));
+    bench("pretty", || {
+        Ok(ser::to_string_with_formatter(&board, PrettyFormatter::new(100))?.len())
+    });
+    bench("SexpWriter", || {
+        let mut w = ser::SexpWriter::new(Count(0));
+        w.value(&board)?;
+        Ok(w.finish()?.0)
+    });
+}
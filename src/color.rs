@@ -0,0 +1,273 @@
+// (c) 2017 Productize SPRL <joost@productize.be>
+
+use std::env;
+use std::io::{self, IsTerminal};
+use std::mem;
+use std::str;
+
+use Sexp;
+use error::SexpError;
+use formatter::{Formatter, LineEnding};
+
+// parenthesis colors by depth when `Colors::rainbow` is set
+const RAINBOW: &[&str] = &["33", "35", "36", "32", "34", "31"];
+
+/// ANSI colors of the parts of a symbolic-expression, as SGR parameters
+/// like `1;34` for bold blue; an empty string leaves a part uncolored
+#[derive(Debug, Clone, PartialEq)]
+pub struct Colors {
+    /// the first string of a list
+    pub head: &'static str,
+    /// strings that are numbers
+    pub number: &'static str,
+    /// quoted strings
+    pub string: &'static str,
+    /// other strings
+    pub symbol: &'static str,
+    /// parentheses
+    pub paren: &'static str,
+    /// color parentheses by depth instead of with `paren`
+    pub rainbow: bool,
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Colors {
+            head: "1;34",
+            number: "36",
+            string: "32",
+            symbol: "",
+            paren: "2",
+            rainbow: false,
+        }
+    }
+}
+
+// where the output of the wrapped formatter is in the symbolic-expression
+#[derive(Default)]
+struct Lexer {
+    depth: usize,
+    // the string being written, colored once it is complete
+    atom: Vec<u8>,
+    quoted: bool,
+    escape: bool,
+    // the next string is the first of a list
+    head: bool,
+}
+
+// writer that colors the output of the wrapped formatter
+struct Painter<'a, W: 'a> {
+    writer: &'a mut W,
+    lexer: &'a mut Lexer,
+    colors: &'a Colors,
+}
+
+impl<'a, W: io::Write> Painter<'a, W> {
+    fn paint(&self, out: &mut Vec<u8>, color: &str, text: &[u8]) {
+        if color.is_empty() {
+            out.extend_from_slice(text);
+        } else {
+            out.extend_from_slice(b"\x1b[");
+            out.extend_from_slice(color.as_bytes());
+            out.push(b'm');
+            out.extend_from_slice(text);
+            out.extend_from_slice(b"\x1b[0m");
+        }
+    }
+
+    fn paren(&self, out: &mut Vec<u8>, c: u8) {
+        let color = if self.colors.rainbow {
+            RAINBOW[self.lexer.depth % RAINBOW.len()]
+        } else {
+            self.colors.paren
+        };
+        self.paint(out, color, &[c]);
+    }
+
+    fn end_atom(&mut self, out: &mut Vec<u8>) {
+        let atom = mem::take(&mut self.lexer.atom);
+        let color = if self.lexer.head {
+            self.colors.head
+        } else if self.lexer.quoted {
+            self.colors.string
+        } else if str::from_utf8(&atom).ok().and_then(|s| s.parse::<f64>().ok()).is_some() {
+            self.colors.number
+        } else {
+            self.colors.symbol
+        };
+        self.paint(out, color, &atom);
+        self.lexer.head = false;
+        self.lexer.quoted = false;
+    }
+
+    fn lex(&mut self, out: &mut Vec<u8>, c: u8) {
+        if self.lexer.quoted && !self.lexer.atom.is_empty() {
+            self.lexer.atom.push(c);
+            if c == b'\\' {
+                self.lexer.escape = true;
+            } else if c == b'"' && !self.lexer.escape {
+                self.end_atom(out);
+            } else {
+                self.lexer.escape = false;
+            }
+            return;
+        }
+        let ends_atom = c == b' ' || c == b'(' || c == b')' || c == b'\r' || c == b'\n';
+        if !self.lexer.atom.is_empty() {
+            if !ends_atom {
+                self.lexer.atom.push(c);
+                return;
+            }
+            self.end_atom(out);
+        }
+        match c {
+            b'(' => {
+                self.paren(out, c);
+                self.lexer.depth += 1;
+                self.lexer.head = true;
+            }
+            b')' => {
+                self.lexer.depth = self.lexer.depth.saturating_sub(1);
+                self.lexer.head = false;
+                self.paren(out, c);
+            }
+            b' ' | b'\t' | b'\r' | b'\n' => out.push(c),
+            _ => {
+                self.lexer.quoted = c == b'"';
+                self.lexer.escape = false;
+                self.lexer.atom.push(c);
+            }
+        }
+    }
+
+    // a string at the root isn't ended by anything that follows
+    fn finish(mut self) -> Result<(), SexpError> {
+        if self.lexer.depth == 0 && !self.lexer.atom.is_empty() {
+            let mut out = vec![];
+            self.end_atom(&mut out);
+            self.writer.write_all(&out)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, W: io::Write> io::Write for Painter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut out = Vec::with_capacity(buf.len());
+        for &c in buf {
+            self.lex(&mut out, c);
+        }
+        self.writer.write_all(&out)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Formatter that adds ANSI colors to the output of another `Formatter`
+/// for reading symbolic-expressions in a terminal
+pub struct ColorFormatter<F> {
+    formatter: F,
+    colors: Colors,
+    enabled: bool,
+    lexer: Lexer,
+}
+
+impl<F: Formatter> ColorFormatter<F> {
+    /// color the output of `formatter`
+    pub fn new(formatter: F) -> ColorFormatter<F> {
+        ColorFormatter {
+            formatter,
+            colors: Colors::default(),
+            enabled: true,
+            lexer: Lexer::default(),
+        }
+    }
+
+    /// only color the output of `formatter` when standard output is a
+    /// terminal and the `NO_COLOR` environment variable isn't set
+    pub fn auto(formatter: F) -> ColorFormatter<F> {
+        let enabled = io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+        ColorFormatter::new(formatter).with_enabled(enabled)
+    }
+
+    /// use `colors` instead of the default colors
+    pub fn with_colors(mut self, colors: Colors) -> ColorFormatter<F> {
+        self.colors = colors;
+        self
+    }
+
+    /// leave the output of the formatter as it is when `enabled` is false
+    pub fn with_enabled(mut self, enabled: bool) -> ColorFormatter<F> {
+        self.enabled = enabled;
+        self
+    }
+
+    /// unwrap the `Formatter`
+    pub fn into_inner(self) -> F {
+        self.formatter
+    }
+
+    // run `f` on the wrapped formatter with a writer that colors its output
+    fn paint<W, G>(&mut self, writer: &mut W, f: G) -> Result<(), SexpError>
+    where
+        W: io::Write,
+        G: FnOnce(&mut F, &mut Painter<W>) -> Result<(), SexpError>,
+    {
+        let mut painter = Painter {
+            writer,
+            lexer: &mut self.lexer,
+            colors: &self.colors,
+        };
+        f(&mut self.formatter, &mut painter)?;
+        painter.finish()
+    }
+}
+
+impl<F: Formatter> Formatter for ColorFormatter<F> {
+    fn open<W>(&mut self, writer: &mut W, value: Option<&Sexp>) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        if !self.enabled {
+            return self.formatter.open(writer, value);
+        }
+        self.paint(writer, |f, w| f.open(w, value))
+    }
+
+    fn element<W>(&mut self, writer: &mut W, value: &Sexp) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        if !self.enabled {
+            return self.formatter.element(writer, value);
+        }
+        self.paint(writer, |f, w| f.element(w, value))
+    }
+
+    fn close<W>(&mut self, writer: &mut W) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        if !self.enabled {
+            return self.formatter.close(writer);
+        }
+        self.paint(writer, |f, w| f.close(w))
+    }
+
+    fn atom<W>(&mut self, writer: &mut W, text: &str) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        if !self.enabled {
+            return self.formatter.atom(writer, text);
+        }
+        self.paint(writer, |f, w| f.atom(w, text))
+    }
+
+    fn set_whitespace(&mut self, indent: Option<&str>, line_ending: Option<LineEnding>) {
+        self.formatter.set_whitespace(indent, line_ending)
+    }
+}
@@ -0,0 +1,151 @@
+// (c) 2017 Productize SPRL <joost@productize.be>
+
+use std::io;
+use std::io::prelude::*;
+
+#[cfg(feature = "gzip")]
+use flate2::bufread::MultiGzDecoder;
+#[cfg(feature = "gzip")]
+use flate2::write::GzEncoder;
+
+use error::SexpError;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// compression formats that can be read and written transparently
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// detect the compression from the magic bytes at the start of `data`
+    pub fn detect(data: &[u8]) -> Compression {
+        if data.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if data.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// derive the compression from the extension of a file name
+    pub fn from_name(name: &str) -> Compression {
+        if name.ends_with(".gz") {
+            Compression::Gzip
+        } else if name.ends_with(".zst") || name.ends_with(".zstd") {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+#[cfg(not(all(feature = "gzip", feature = "zstd")))]
+fn not_enabled<T>(feature: &str) -> Result<T, SexpError> {
+    Err(format!(
+        "{} compression support is not enabled, enable the `{}` feature",
+        feature, feature
+    ).into())
+}
+
+/// a reader that decompresses what is read from it when the stream
+/// starts with the magic bytes of a known compression format
+pub enum Reader<R: BufRead> {
+    Plain(R),
+    #[cfg(feature = "gzip")]
+    Gzip(MultiGzDecoder<R>),
+    #[cfg(feature = "zstd")]
+    Zstd(::zstd::Decoder<'static, R>),
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(mut inner: R) -> Result<Reader<R>, SexpError> {
+        // the magic bytes are only peeked at, the decoder reads them again
+        match Compression::detect(inner.fill_buf()?) {
+            Compression::None => Ok(Reader::Plain(inner)),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Ok(Reader::Gzip(MultiGzDecoder::new(inner))),
+            #[cfg(not(feature = "gzip"))]
+            Compression::Gzip => not_enabled("gzip"),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(Reader::Zstd(::zstd::Decoder::with_buffer(inner)?)),
+            #[cfg(not(feature = "zstd"))]
+            Compression::Zstd => not_enabled("zstd"),
+        }
+    }
+}
+
+impl<R: BufRead> Read for Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Reader::Plain(ref mut r) => r.read(buf),
+            #[cfg(feature = "gzip")]
+            Reader::Gzip(ref mut r) => r.read(buf),
+            #[cfg(feature = "zstd")]
+            Reader::Zstd(ref mut r) => r.read(buf),
+        }
+    }
+}
+
+/// a writer that compresses what is written to it
+pub enum Writer<W: Write> {
+    Plain(W),
+    #[cfg(feature = "gzip")]
+    Gzip(GzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(::zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(inner: W, compression: Compression) -> Result<Writer<W>, SexpError> {
+        match compression {
+            Compression::None => Ok(Writer::Plain(inner)),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Ok(Writer::Gzip(GzEncoder::new(inner, Default::default()))),
+            #[cfg(not(feature = "gzip"))]
+            Compression::Gzip => not_enabled("gzip"),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(Writer::Zstd(::zstd::Encoder::new(inner, 0)?)),
+            #[cfg(not(feature = "zstd"))]
+            Compression::Zstd => not_enabled("zstd"),
+        }
+    }
+
+    /// write the end of the compressed stream and return the inner writer
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Writer::Plain(w) => Ok(w),
+            #[cfg(feature = "gzip")]
+            Writer::Gzip(w) => w.finish(),
+            #[cfg(feature = "zstd")]
+            Writer::Zstd(w) => w.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Writer::Plain(ref mut w) => w.write(buf),
+            #[cfg(feature = "gzip")]
+            Writer::Gzip(ref mut w) => w.write(buf),
+            #[cfg(feature = "zstd")]
+            Writer::Zstd(ref mut w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Writer::Plain(ref mut w) => w.flush(),
+            #[cfg(feature = "gzip")]
+            Writer::Gzip(ref mut w) => w.flush(),
+            #[cfg(feature = "zstd")]
+            Writer::Zstd(ref mut w) => w.flush(),
+        }
+    }
+}
@@ -0,0 +1,370 @@
+// (c) 2017 Productize SPRL <joost@productize.be>
+
+use std::fmt;
+use std::slice::Iter;
+
+use Sexp;
+use error::SexpError;
+use parser;
+use scanner::{Event, Scanner};
+use ser;
+use sexp;
+
+enum Kind {
+    // the string as it is in the text, including quotes
+    Atom(String),
+    // `gaps[i]` is the text before child `i`, the last one the text before ')'
+    List { children: Vec<Node>, gaps: Vec<String> },
+}
+
+/// a list or string of a `Document` that remembers its layout
+pub struct Node {
+    kind: Kind,
+}
+
+impl Node {
+    fn atom(raw: &str) -> Node {
+        Node {
+            kind: Kind::Atom(raw.into()),
+        }
+    }
+
+    // an atom with the string `value` in quotes
+    fn quoted(value: &str) -> Node {
+        let mut raw = String::with_capacity(value.len() + 2);
+        sexp::push_quoted(&mut raw, value);
+        Node::atom(&raw)
+    }
+
+    fn list() -> Node {
+        Node {
+            kind: Kind::List {
+                children: vec![],
+                gaps: vec![],
+            },
+        }
+    }
+
+    // a node for a new value, written on one line
+    fn from_sexp(value: &Sexp) -> Result<Node, SexpError> {
+        if let Sexp::Empty = *value {
+            return Err("can't edit in an empty symbolic-expression".into());
+        }
+        let text = ser::to_string(value)?;
+        Ok(Document::parse(&text)?.root)
+    }
+
+    /// is the node a list
+    pub fn is_list(&self) -> bool {
+        match self.kind {
+            Kind::List { .. } => true,
+            Kind::Atom(_) => false,
+        }
+    }
+
+    /// the string of an atom without quotes, or the name of a list
+    /// if it starts with a string
+    pub fn name(&self) -> Option<&str> {
+        match self.kind {
+            Kind::Atom(ref raw) => Some(unquote(raw)),
+            Kind::List { ref children, .. } => match children.first() {
+                Some(&Node {
+                    kind: Kind::Atom(ref raw),
+                }) => Some(unquote(raw)),
+                _ => None,
+            },
+        }
+    }
+
+    /// number of elements of a list, 0 for a string
+    pub fn len(&self) -> usize {
+        self.children().len()
+    }
+
+    /// does the node have no elements
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// the element at `index` of a list
+    pub fn get(&self, index: usize) -> Option<&Node> {
+        self.children().get(index)
+    }
+
+    /// the element at `index` of a list, for editing
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Node> {
+        match self.kind {
+            Kind::List {
+                ref mut children, ..
+            } => children.get_mut(index),
+            Kind::Atom(_) => None,
+        }
+    }
+
+    /// iterate over the elements of a list
+    pub fn iter(&self) -> Iter<'_, Node> {
+        self.children().iter()
+    }
+
+    /// the first element that is a list named `name`
+    pub fn find(&self, name: &str) -> Option<&Node> {
+        self.iter().find(|c| c.is_list() && c.name() == Some(name))
+    }
+
+    /// the first element that is a list named `name`, for editing
+    pub fn find_mut(&mut self, name: &str) -> Option<&mut Node> {
+        match self.kind {
+            Kind::List {
+                ref mut children, ..
+            } => children.iter_mut().find(|c| c.is_list() && c.name() == Some(name)),
+            Kind::Atom(_) => None,
+        }
+    }
+
+    /// all elements that are lists named `name`
+    pub fn find_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Node> {
+        self.iter().filter(move |c| c.is_list() && c.name() == Some(name))
+    }
+
+    /// parse the node
+    pub fn to_sexp(&self) -> Result<Sexp, SexpError> {
+        parser::parse_str(&self.to_string())
+    }
+
+    /// replace the node with `value`
+    pub fn set(&mut self, value: &Sexp) -> Result<(), SexpError> {
+        *self = Node::from_sexp(value)?;
+        Ok(())
+    }
+
+    /// replace the node with the string `value`, quoted even where the
+    /// quoting rules wouldn't, like KiCad 8 wants for `(generator_version "8.0")`
+    pub fn set_quoted(&mut self, value: &str) -> Result<(), SexpError> {
+        *self = Node::quoted(value);
+        Ok(())
+    }
+
+    /// insert `value` at `index` of a list, separated from its neighbours
+    /// like the elements around it
+    pub fn insert(&mut self, index: usize, value: &Sexp) -> Result<(), SexpError> {
+        let node = Node::from_sexp(value)?;
+        self.insert_node(index, node)
+    }
+
+    /// insert the string `value` at `index` of a list, quoted even where
+    /// the quoting rules wouldn't
+    pub fn insert_quoted(&mut self, index: usize, value: &str) -> Result<(), SexpError> {
+        self.insert_node(index, Node::quoted(value))
+    }
+
+    fn insert_node(&mut self, index: usize, node: Node) -> Result<(), SexpError> {
+        let (children, gaps) = match self.kind {
+            Kind::List {
+                ref mut children,
+                ref mut gaps,
+            } => (children, gaps),
+            Kind::Atom(_) => return Err("can't insert in a string".into()),
+        };
+        let n = children.len();
+        if index > n {
+            return Err(format!("index {} out of range for a list of {}", index, n).into());
+        }
+        if n == 0 {
+            gaps.insert(0, String::new());
+        } else if index == 0 {
+            let separator = if n > 1 { gaps[1].clone() } else { " ".into() };
+            gaps.insert(1, separator);
+        } else if index == n {
+            let separator = if n > 1 { gaps[n - 1].clone() } else { " ".into() };
+            gaps.insert(n, separator);
+        } else {
+            let separator = gaps[index].clone();
+            gaps.insert(index, separator);
+        }
+        children.insert(index, node);
+        Ok(())
+    }
+
+    /// append `value` to a list
+    pub fn push(&mut self, value: &Sexp) -> Result<(), SexpError> {
+        let n = self.len();
+        self.insert(n, value)
+    }
+
+    /// remove the element at `index` of a list, with the whitespace before it,
+    /// or after it for the first element
+    ///
+    /// A list left with one element or none is closed right after it.
+    pub fn remove(&mut self, index: usize) -> Result<(), SexpError> {
+        let (children, gaps) = match self.kind {
+            Kind::List {
+                ref mut children,
+                ref mut gaps,
+            } => (children, gaps),
+            Kind::Atom(_) => return Err("can't remove from a string".into()),
+        };
+        if index >= children.len() {
+            let msg = format!("index {} out of range for a list of {}", index, children.len());
+            return Err(msg.into());
+        }
+        children.remove(index);
+        // `gaps[0]` is the whitespace after '(', which the first element keeps
+        gaps.remove(if index == 0 && !children.is_empty() { 1 } else { index });
+        if children.len() <= 1 {
+            gaps.last_mut().expect("the gap before ')'").clear();
+        }
+        Ok(())
+    }
+
+    fn children(&self) -> &[Node] {
+        match self.kind {
+            Kind::List { ref children, .. } => children,
+            Kind::Atom(_) => &[],
+        }
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self.kind {
+            Kind::Atom(ref raw) => f.write_str(raw),
+            Kind::List {
+                ref children,
+                ref gaps,
+            } => {
+                f.write_str("(")?;
+                for (gap, child) in gaps.iter().zip(children) {
+                    f.write_str(gap)?;
+                    child.fmt(f)?;
+                }
+                f.write_str(&gaps[children.len()])?;
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+/// a symbolic-expression text that can be edited while keeping the layout,
+/// so only the edited elements are written differently
+///
+/// New elements are written on one line; when they are inserted in a list
+/// they are separated from their neighbours like the other elements are.
+pub struct Document {
+    leading: String,
+    root: Node,
+    trailing: String,
+}
+
+impl Document {
+    /// parse a &str into an editable document
+    pub fn parse(text: &str) -> Result<Document, SexpError> {
+        let mut scanner = Scanner::new(text);
+        scanner.eat_space();
+        if scanner.eof() {
+            return Err("can't edit in an empty symbolic-expression".into());
+        }
+        let start = scanner.position().offset;
+        // the open lists, where they start and the end of their last element
+        let mut stack: Vec<(Node, usize, usize)> = vec![];
+        let mut root = None;
+        let mut end = start;
+        scanner.walk(&mut |event| {
+            let (node, node_end) = match event {
+                Event::Open(p) => {
+                    stack.push((Node::list(), p.offset, p.offset + 1));
+                    return;
+                }
+                Event::Atom(p, raw) => {
+                    let node = Node::atom(raw);
+                    match stack.last_mut() {
+                        Some(&mut (ref mut list, _, ref mut last)) => {
+                            add(list, &text[*last..p.offset], node);
+                            *last = p.offset + raw.len();
+                            return;
+                        }
+                        None => (node, p.offset + raw.len()),
+                    }
+                }
+                Event::Close(p) => {
+                    let (mut list, start, last) = stack.pop().expect("balanced lists");
+                    if let Kind::List { ref mut gaps, .. } = list.kind {
+                        gaps.push(text[last..p.offset - 1].into());
+                    }
+                    match stack.last_mut() {
+                        Some(&mut (ref mut parent, _, ref mut parent_last)) => {
+                            add(parent, &text[*parent_last..start], list);
+                            *parent_last = p.offset;
+                            return;
+                        }
+                        None => (list, p.offset),
+                    }
+                }
+            };
+            root = Some(node);
+            end = node_end;
+        })?;
+        Ok(Document {
+            leading: text[..start].into(),
+            root: root.expect("a root element"),
+            trailing: text[end..].into(),
+        })
+    }
+
+    /// load a file into an editable document
+    pub fn load(name: &str) -> Result<Document, SexpError> {
+        Document::parse(&parser::read_file(name)?)
+    }
+
+    /// the root element
+    pub fn root(&self) -> &Node {
+        &self.root
+    }
+
+    /// the root element, for editing
+    pub fn root_mut(&mut self) -> &mut Node {
+        &mut self.root
+    }
+
+    /// the element at `path`, the position in each list from the root
+    pub fn get(&self, path: &[usize]) -> Option<&Node> {
+        path.iter().try_fold(&self.root, |node, &i| node.get(i))
+    }
+
+    /// the element at `path`, for editing
+    pub fn get_mut(&mut self, path: &[usize]) -> Option<&mut Node> {
+        path.iter().try_fold(&mut self.root, |node, &i| node.get_mut(i))
+    }
+
+    /// parse the document
+    pub fn to_sexp(&self) -> Result<Sexp, SexpError> {
+        self.root.to_sexp()
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.write_str(&self.leading)?;
+        self.root.fmt(f)?;
+        f.write_str(&self.trailing)
+    }
+}
+
+// add `node` to `list`, preceded by `gap`
+fn add(list: &mut Node, gap: &str, node: Node) {
+    if let Kind::List {
+        ref mut children,
+        ref mut gaps,
+    } = list.kind
+    {
+        gaps.push(gap.into());
+        children.push(node);
+    }
+}
+
+fn unquote(raw: &str) -> &str {
+    if raw.len() > 1 && raw.starts_with('"') && raw.ends_with('"') {
+        &raw[1..raw.len() - 1]
+    } else {
+        raw
+    }
+}
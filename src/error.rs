@@ -1,5 +1,6 @@
 // (c) 2016-2017 Productize SPRL <joost@productize.be>
 
+use std::str;
 use std::string;
 use std::io;
 use std::num;
@@ -7,7 +8,10 @@ use std::error;
 use std::fmt;
 
 /// errors that can happen in this library
+///
+/// New kinds of errors may be added, so a `match` needs a `_` arm.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum SexpError {
     /// parse error
     Parse(ParseError),
@@ -17,6 +21,8 @@ pub enum SexpError {
     Io(io::Error),
     /// Utf8 Error parsing error
     FromUtf8(string::FromUtf8Error),
+    /// Utf8 Error parsing error for borrowed data
+    Utf8(str::Utf8Error),
     /// floating point parsing error
     Float(num::ParseFloatError),
     /// integer parsing error
@@ -31,6 +37,7 @@ impl fmt::Display for SexpError {
             SexpError::Other(ref s) => write!(f, "symbolic expression other error: {}", s),
             SexpError::Io(ref e) => e.fmt(f),
             SexpError::FromUtf8(ref e) => e.fmt(f),
+            SexpError::Utf8(ref e) => e.fmt(f),
             SexpError::Float(ref e) => e.fmt(f),
             SexpError::Int(ref e) => e.fmt(f),
         }
@@ -43,11 +50,12 @@ impl error::Error for SexpError {
         "symbolic expressions error"
     }
 
-    fn cause(&self) -> Option<&error::Error> {
+    fn cause(&self) -> Option<&dyn error::Error> {
         match *self {
             SexpError::Parse(_) | SexpError::Other(_) => None,
             SexpError::Io(ref e) => Some(e),
             SexpError::FromUtf8(ref e) => Some(e),
+            SexpError::Utf8(ref e) => Some(e),
             SexpError::Float(ref e) => Some(e),
             SexpError::Int(ref e) => Some(e),
         }
@@ -62,6 +70,23 @@ pub struct ParseError {
     col: usize,
 }
 
+impl ParseError {
+    /// description of what went wrong
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+
+    /// line (starting from 1) where the error was detected
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// column (starting from 1) where the error was detected
+    pub fn col(&self) -> usize {
+        self.col
+    }
+}
+
 impl From<io::Error> for SexpError {
     fn from(e: io::Error) -> SexpError {
         SexpError::Io(e)
@@ -86,6 +111,12 @@ impl From<string::FromUtf8Error> for SexpError {
     }
 }
 
+impl From<str::Utf8Error> for SexpError {
+    fn from(e: str::Utf8Error) -> SexpError {
+        SexpError::Utf8(e)
+    }
+}
+
 impl From<num::ParseFloatError> for SexpError {
     fn from(e: num::ParseFloatError) -> SexpError {
         SexpError::Float(e)
@@ -101,9 +132,9 @@ impl From<num::ParseIntError> for SexpError {
 /// utility function that creates a symbolic-expressions Error Result for a parser error
 pub fn parse_error<T>(line: usize, col: usize, msg: String) -> Result<T, SexpError> {
     let pe = ParseError {
-        msg: msg,
-        line: line,
-        col: col,
+        msg,
+        line,
+        col,
     };
     Err(SexpError::Parse(pe))
 }
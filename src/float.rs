@@ -0,0 +1,74 @@
+// (c) 2017 Productize SPRL <joost@productize.be>
+
+use Sexp;
+
+/// how floating point numbers are written
+///
+/// None of the formats ever use scientific notation.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum FloatFormat {
+    /// the shortest representation that parses back to the same number
+    #[default]
+    Shortest,
+    /// a fixed number of decimals
+    Fixed(usize),
+    /// at most a number of decimals, without trailing zeros
+    Trimmed(usize),
+    /// like KiCad: 10 decimals, or 16 for very small numbers,
+    /// without trailing zeros
+    Kicad,
+}
+
+impl FloatFormat {
+    /// format `f`
+    ///
+    /// NaN and infinity are written as `NaN`, `inf` and `-inf`.
+    pub fn format(&self, f: f64) -> String {
+        match *self {
+            FloatFormat::Shortest => format!("{}", f),
+            FloatFormat::Fixed(decimals) => format!("{:.*}", decimals, f),
+            FloatFormat::Trimmed(decimals) => trim(format!("{:.*}", decimals, f)),
+            FloatFormat::Kicad => {
+                let decimals = if f != 0.0 && f.abs() <= 0.0001 { 16 } else { 10 };
+                trim(format!("{:.*}", decimals, f))
+            }
+        }
+    }
+
+    /// format `f` as a string symbolic-expression
+    pub fn to_sexp(&self, f: f64) -> Sexp {
+        Sexp::String(self.format(f))
+    }
+
+    /// format `s` again if it is a decimal number with a fraction or an
+    /// exponent with a sign; hexadecimal like `12345E67` isn't a number
+    pub(crate) fn reformat(&self, s: &str) -> Option<String> {
+        let signed_exponent = ["e-", "e+", "E-", "E+"].iter().any(|e| s.contains(e));
+        if !s.contains('.') && !signed_exponent {
+            return None;
+        }
+        self.reformat_number(s)
+    }
+
+    /// format `s` again if it is a number, including integers
+    pub(crate) fn reformat_number(&self, s: &str) -> Option<String> {
+        let numeric = s.bytes()
+            .all(|c| matches!(c, b'0'..=b'9' | b'.' | b'-' | b'+' | b'e' | b'E'));
+        if !numeric {
+            return None;
+        }
+        s.parse().ok().map(|f| self.format(f))
+    }
+}
+
+// remove trailing zeros after the decimal point
+fn trim(mut s: String) -> String {
+    if s.contains('.') {
+        let l = s.trim_end_matches('0').trim_end_matches('.').len();
+        s.truncate(l);
+    }
+    if s == "-0" {
+        s.remove(0);
+    }
+    s
+}
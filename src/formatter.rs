@@ -1,11 +1,133 @@
 // (c) 2016 Productize SPRL <joost@productize.be>
 
+use std::fmt;
 use std::io;
-use std::collections::HashMap;
+use std::str;
+use std::collections::{HashMap, VecDeque};
 
 use error::SexpError;
 use Sexp;
 
+/// where a `ContextFormatter` writes the text of a symbolic-expression
+///
+/// The text is written as `&str`, so a `String` or a `fmt::Write` gets it
+/// without checking it is UTF-8 again. A `Formatter` writes to an
+/// `io::Write` instead, which `Adapter` passes on with `write_bytes`.
+pub trait Sink {
+    /// write a piece of text
+    fn write_str(&mut self, s: &str) -> Result<(), SexpError>;
+
+    /// write a piece of text given as UTF-8 bytes, like a `Formatter` writes
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), SexpError> {
+        match str::from_utf8(bytes) {
+            Ok(s) => self.write_str(s),
+            Err(_) => Err("a formatter wrote text that isn't UTF-8".into()),
+        }
+    }
+}
+
+impl Sink for String {
+    fn write_str(&mut self, s: &str) -> Result<(), SexpError> {
+        self.push_str(s);
+        Ok(())
+    }
+}
+
+impl<S: Sink + ?Sized> Sink for &mut S {
+    fn write_str(&mut self, s: &str) -> Result<(), SexpError> {
+        (**self).write_str(s)
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), SexpError> {
+        (**self).write_bytes(bytes)
+    }
+}
+
+// a `Sink` that writes to an `io::Write`
+pub(crate) struct IoSink<W>(pub W);
+
+impl<W: io::Write> Sink for IoSink<W> {
+    fn write_str(&mut self, s: &str) -> Result<(), SexpError> {
+        self.write_bytes(s.as_bytes())
+    }
+
+    // the bytes go to the writer as they are
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), SexpError> {
+        self.0.write_all(bytes)?;
+        Ok(())
+    }
+}
+
+// an `io::Write` that passes what a `Formatter` writes on to a `Sink`
+struct SinkWriter<'a, W: 'a + ?Sized>(&'a mut W);
+
+impl<'a, W: Sink + ?Sized> io::Write for SinkWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    #[inline]
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self.0.write_bytes(buf) {
+            Ok(()) => Ok(()),
+            Err(SexpError::Io(e)) => Err(e),
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// a `Sink` that collects a `String`; what a `Formatter` writes is checked
+// to be UTF-8 once at the end, which is a lot faster than for every piece
+pub(crate) struct StringSink {
+    bytes: Vec<u8>,
+    unchecked: bool,
+}
+
+impl StringSink {
+    pub fn new() -> StringSink {
+        StringSink {
+            bytes: Vec::with_capacity(128),
+            unchecked: false,
+        }
+    }
+
+    pub fn into_string(self) -> Result<String, SexpError> {
+        if self.unchecked {
+            return Ok(String::from_utf8(self.bytes)?);
+        }
+        // only `&str` was written
+        Ok(unsafe { String::from_utf8_unchecked(self.bytes) })
+    }
+}
+
+impl Sink for StringSink {
+    fn write_str(&mut self, s: &str) -> Result<(), SexpError> {
+        self.bytes.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+
+    #[inline]
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), SexpError> {
+        self.bytes.extend_from_slice(bytes);
+        self.unchecked = true;
+        Ok(())
+    }
+}
+
+// a `Sink` that writes to a `fmt::Write`
+pub(crate) struct FmtSink<W>(pub W);
+
+impl<W: fmt::Write> Sink for FmtSink<W> {
+    fn write_str(&mut self, s: &str) -> Result<(), SexpError> {
+        self.0.write_str(s).map_err(|_| "formatting failed".into())
+    }
+}
+
 /// trait for formatting the serialization of a symbolic-expression
 pub trait Formatter {
     /// Called when serializing a '('.
@@ -22,8 +144,205 @@ pub trait Formatter {
     fn close<W>(&mut self, writer: &mut W) -> Result<(), SexpError>
     where
         W: io::Write;
+
+    /// Called when serializing a string, with the already encoded `text`.
+    fn atom<W>(&mut self, writer: &mut W, text: &str) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        writer.write_all(text.as_bytes())?;
+        Ok(())
+    }
+
+    /// Called before serializing, with the indentation and line ending to use;
+    /// `None` keeps what the formatter uses by default.
+    fn set_whitespace(&mut self, _indent: Option<&str>, _line_ending: Option<LineEnding>) {}
+}
+
+/// how lines are ended
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LineEnding {
+    /// `\n`
+    #[default]
+    Lf,
+    /// `\r\n`, like on Windows
+    CrLf,
+}
+
+impl LineEnding {
+    /// the characters that end a line
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+/// the location of a value that is being serialized
+pub struct Context<'a> {
+    heads: &'a [&'a str],
+    index: usize,
+    len: usize,
 }
 
+impl<'a> Context<'a> {
+    pub(crate) fn new(heads: &'a [&'a str], index: usize, len: usize) -> Context<'a> {
+        Context { heads, index, len }
+    }
+
+    /// number of lists containing the value
+    pub fn depth(&self) -> usize {
+        self.heads.len()
+    }
+
+    /// names of the lists containing the value, outermost first;
+    /// empty for lists that don't start with a string
+    pub fn heads(&self) -> &'a [&'a str] {
+        self.heads
+    }
+
+    /// name of the list containing the value
+    pub fn parent(&self) -> Option<&'a str> {
+        self.heads.last().cloned()
+    }
+
+    /// position of the value in the containing list
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// number of values in the containing list, including this one
+    pub fn siblings(&self) -> usize {
+        self.len
+    }
+
+    /// is the value the last of the containing list
+    pub fn is_last(&self) -> bool {
+        self.index + 1 == self.len
+    }
+}
+
+/// trait for formatting the serialization of a symbolic-expression
+/// that knows where in the symbolic-expression it is
+pub trait ContextFormatter {
+    /// Called when serializing the '(' of `list`.
+    fn open<W>(&mut self, writer: &mut W, context: &Context, list: &[Sexp]) -> Result<(), SexpError>
+    where
+        W: Sink;
+
+    /// Called before serializing a `value` that isn't the first of its list.
+    fn element<W>(
+        &mut self,
+        writer: &mut W,
+        context: &Context,
+        value: &Sexp,
+    ) -> Result<(), SexpError>
+    where
+        W: Sink;
+
+    /// Called when serializing the ')' of `list`.
+    fn close<W>(
+        &mut self,
+        writer: &mut W,
+        context: &Context,
+        list: &[Sexp],
+    ) -> Result<(), SexpError>
+    where
+        W: Sink;
+
+    /// Called when serializing the string `value`, encoded as `text`.
+    fn atom<W>(
+        &mut self,
+        writer: &mut W,
+        _context: &Context,
+        _value: &str,
+        text: &str,
+    ) -> Result<(), SexpError>
+    where
+        W: Sink,
+    {
+        writer.write_str(text)
+    }
+
+    /// Called before serializing, with the indentation and line ending to use;
+    /// `None` keeps what the formatter uses by default.
+    fn set_whitespace(&mut self, _indent: Option<&str>, _line_ending: Option<LineEnding>) {}
+}
+
+/// use a `Formatter` where a `ContextFormatter` is expected
+pub struct Adapter<F> {
+    formatter: F,
+}
+
+impl<F: Formatter> Adapter<F> {
+    /// wrap a `Formatter`
+    pub fn new(formatter: F) -> Adapter<F> {
+        Adapter { formatter }
+    }
+
+    /// unwrap the `Formatter`
+    pub fn into_inner(self) -> F {
+        self.formatter
+    }
+}
+
+impl<F: Formatter> ContextFormatter for Adapter<F> {
+    fn open<W>(
+        &mut self,
+        writer: &mut W,
+        _context: &Context,
+        list: &[Sexp],
+    ) -> Result<(), SexpError>
+    where
+        W: Sink,
+    {
+        self.formatter.open(&mut SinkWriter(writer), list.first())
+    }
+
+    fn element<W>(
+        &mut self,
+        writer: &mut W,
+        _context: &Context,
+        value: &Sexp,
+    ) -> Result<(), SexpError>
+    where
+        W: Sink,
+    {
+        self.formatter.element(&mut SinkWriter(writer), value)
+    }
+
+    fn close<W>(
+        &mut self,
+        writer: &mut W,
+        _context: &Context,
+        _list: &[Sexp],
+    ) -> Result<(), SexpError>
+    where
+        W: Sink,
+    {
+        self.formatter.close(&mut SinkWriter(writer))
+    }
+
+    fn atom<W>(
+        &mut self,
+        writer: &mut W,
+        _context: &Context,
+        _value: &str,
+        text: &str,
+    ) -> Result<(), SexpError>
+    where
+        W: Sink,
+    {
+        self.formatter.atom(&mut SinkWriter(writer), text)
+    }
+
+    fn set_whitespace(&mut self, indent: Option<&str>, line_ending: Option<LineEnding>) {
+        self.formatter.set_whitespace(indent, line_ending)
+    }
+}
+
+/// Formatter that puts everything on one line with single spaces
 pub struct CompactFormatter;
 
 impl Formatter for CompactFormatter {
@@ -31,46 +350,327 @@ impl Formatter for CompactFormatter {
     where
         W: io::Write,
     {
-        writer.write_all(b"(").map_err(From::from)
+        writer.write_all(b"(")?;
+        Ok(())
     }
     fn element<W>(&mut self, writer: &mut W, _value: &Sexp) -> Result<(), SexpError>
     where
         W: io::Write,
     {
-        writer.write_all(b" ").map_err(From::from)
+        writer.write_all(b" ")?;
+        Ok(())
     }
 
     fn close<W>(&mut self, writer: &mut W) -> Result<(), SexpError>
     where
         W: io::Write,
     {
-        writer.write_all(b")").map_err(From::from)
+        writer.write_all(b")")?;
+        Ok(())
     }
 }
 
-/// datatype used by the example Formatter `RulesFormatter`
+/// datatype used by the example Formatter `RulesFormatter`:
+/// the number of indents for lists with a name that start on a new line
 pub type Rules = HashMap<&'static str, i64>;
 
+/// how far a list that starts on a new line is indented
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Indent {
+    /// a fixed number of indents
+    Absolute(usize),
+    /// a number of indents more than the line on which the parent list starts
+    Relative(usize),
+}
+
+/// layout of a list, selected by its name or by the path of names leading to it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rule {
+    /// start the list on a new line
+    pub newline_before: bool,
+    /// indentation of the list when it starts on a new line
+    pub indent: Indent,
+    /// put every list contained in this list on its own line
+    pub children_on_own_line: bool,
+    /// put the closing ')' on its own line when the list spans several lines
+    pub close_on_own_line: bool,
+    /// continue on a new line after this many elements
+    pub max_items_per_line: Option<usize>,
+    /// align continuation lines with the first element after the name
+    /// instead of indenting them
+    pub align: bool,
+}
+
+impl Default for Rule {
+    fn default() -> Self {
+        Rule {
+            newline_before: false,
+            indent: Indent::Relative(1),
+            children_on_own_line: false,
+            close_on_own_line: false,
+            max_items_per_line: None,
+            align: false,
+        }
+    }
+}
+
+/// the `Rule`s used by a `RulesFormatter`
+///
+/// A rule for a path like `["module", "pad"]` only applies to `pad` lists
+/// directly in a `module` list, and takes precedence over a rule for `pad`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RuleSet {
+    rules: HashMap<Vec<String>, Rule>,
+    max_path: usize,
+}
+
+impl RuleSet {
+    /// create an empty `RuleSet`
+    pub fn new() -> RuleSet {
+        RuleSet::default()
+    }
+
+    /// set the rule for lists named `name`
+    ///
+    /// shape: (name ...)
+    pub fn insert(&mut self, name: &str, rule: Rule) {
+        self.insert_path(&[name], rule)
+    }
+
+    /// set the rule for lists at the end of a path of names,
+    /// outermost first
+    ///
+    /// shape: (parent ... (name ...) ...)
+    pub fn insert_path(&mut self, path: &[&str], rule: Rule) {
+        self.max_path = self.max_path.max(path.len());
+        self.rules.insert(path.iter().map(|&s| String::from(s)).collect(), rule);
+    }
+
+    /// all rules with their paths, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&[String], &Rule)> {
+        self.rules.iter().map(|(path, rule)| (&path[..], rule))
+    }
+
+    /// is the set empty
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// the most specific rule for the innermost of the lists named `heads`
+    pub fn get(&self, heads: &[String]) -> Option<&Rule> {
+        let l = heads.len();
+        (1..=self.max_path.min(l))
+            .rev()
+            .filter_map(|n| self.rules.get(&heads[l - n..]))
+            .next()
+    }
+}
+
+impl From<Rules> for RuleSet {
+    fn from(rules: Rules) -> RuleSet {
+        let mut set = RuleSet::new();
+        for (name, indent) in rules {
+            let rule = Rule {
+                newline_before: true,
+                indent: Indent::Absolute(indent.max(0) as usize),
+                ..Default::default()
+            };
+            set.insert(name, rule);
+        }
+        set
+    }
+}
+
+// an open list
+struct Frame {
+    rule: Rule,
+    // indentation of the line on which the list starts
+    indent: usize,
+    // column of the first element after the name
+    align_col: Option<usize>,
+    // elements written so far, in total and on the current line
+    count: usize,
+    items: usize,
+    start_line: usize,
+}
+
+/// Formatter that lays out lists according to the `Rule` for their name
 pub struct RulesFormatter {
-    indent: Vec<u8>,
-    indent_before: Rules,
+    indent: String,
+    line_ending: LineEnding,
+    rules: RuleSet,
+    heads: Vec<String>,
+    // names of closed lists, reused for the next lists
+    spare: Vec<String>,
+    stack: Vec<Frame>,
+    // an element() call is waiting for the open() or atom() that follows it
+    separator: bool,
+    width: Option<usize>,
+    // rule of lists without a rule of their own
+    default_rule: Rule,
+    own_line_depth: Option<usize>,
+    col: usize,
+    // column after the indentation of the current line
+    line_start: usize,
+    line: usize,
+    line_indent: usize,
 }
 
 impl Default for RulesFormatter {
     fn default() -> Self {
-        let idb = HashMap::new();
-        RulesFormatter {
-            indent: vec![b' ', b' '], // two spaces
-            indent_before: idb,
-        }
+        RulesFormatter::new(RuleSet::new())
     }
 }
 
 impl RulesFormatter {
-    pub fn new(indent_before: Rules) -> RulesFormatter {
+    /// create a `RulesFormatter` from `Rules` or a `RuleSet`
+    pub fn new<R: Into<RuleSet>>(rules: R) -> RulesFormatter {
         RulesFormatter {
-            indent: vec![b' ', b' '], // two spaces
-            indent_before: indent_before,
+            indent: "  ".into(), // two spaces
+            line_ending: LineEnding::Lf,
+            rules: rules.into(),
+            heads: vec![],
+            spare: vec![],
+            stack: vec![],
+            separator: false,
+            width: None,
+            default_rule: Rule::default(),
+            own_line_depth: None,
+            col: 0,
+            line_start: 0,
+            line: 0,
+            line_indent: 0,
+        }
+    }
+
+    /// indent with `indent` instead of two spaces
+    pub fn with_indent(mut self, indent: &str) -> RulesFormatter {
+        self.indent = indent.into();
+        self
+    }
+
+    /// continue lines that would get longer than `width` characters on a new line
+    pub fn with_width(mut self, width: usize) -> RulesFormatter {
+        self.width = Some(width);
+        self
+    }
+
+    /// lay out lists without a rule of their own with `rule`
+    pub fn with_default_rule(mut self, rule: Rule) -> RulesFormatter {
+        self.default_rule = rule;
+        self
+    }
+
+    /// start lists without a rule of their own on a new line when they are
+    /// nested in at least `depth` lists, unless the containing list limits
+    /// the number of items per line
+    pub fn with_own_line_depth(mut self, depth: usize) -> RulesFormatter {
+        self.own_line_depth = Some(depth);
+        self
+    }
+
+    // the rule of a list without a rule of its own
+    fn fallback_rule(&self) -> Rule {
+        let mut rule = self.default_rule;
+        if let Some(depth) = self.own_line_depth {
+            let wrapped = match self.stack.last() {
+                Some(f) => f.rule.max_items_per_line.is_some(),
+                None => false,
+            };
+            if self.stack.len() >= depth && !wrapped {
+                rule.newline_before = true;
+            }
+        }
+        rule
+    }
+
+    fn write<W>(&mut self, writer: &mut W, s: &str) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        writer.write_all(s.as_bytes())?;
+        self.col += s.chars().count();
+        Ok(())
+    }
+
+    fn newline<W>(&mut self, writer: &mut W, level: usize) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        writer.write_all(self.line_ending.as_str().as_bytes())?;
+        for _ in 0..level {
+            writer.write_all(self.indent.as_bytes())?;
+        }
+        self.col = level * self.indent.len();
+        self.line_start = self.col;
+        self.line += 1;
+        self.line_indent = level;
+        for frame in &mut self.stack {
+            frame.items = 0;
+        }
+        Ok(())
+    }
+
+    // continue the innermost list on a new line
+    fn wrap<W>(&mut self, writer: &mut W) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        let (level, align_col) = match self.stack.last() {
+            Some(f) if f.rule.align => (f.indent + 1, f.align_col),
+            Some(f) => (f.indent + 1, None),
+            None => (0, None),
+        };
+        match align_col {
+            Some(col) => {
+                self.newline(writer, 0)?;
+                self.write(writer, &" ".repeat(col))?;
+                self.line_start = col;
+                self.line_indent = level;
+                Ok(())
+            }
+            None => self.newline(writer, level),
+        }
+    }
+
+    // the whitespace before an element that isn't the first of its list
+    // and starts with `len` characters that should stay on the same line
+    fn separate<W>(&mut self, writer: &mut W, list: bool, len: usize) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        let too_long = match self.width {
+            Some(width) => self.col > self.line_start && self.col + 1 + len > width,
+            None => false,
+        };
+        let wrap = too_long || match self.stack.last() {
+            Some(f) => {
+                (list && f.rule.children_on_own_line)
+                    || f.rule.max_items_per_line.map(|m| f.items >= m).unwrap_or(false)
+            }
+            None => false,
+        };
+        if wrap {
+            self.wrap(writer)?;
+        } else {
+            self.write(writer, " ")?;
+        }
+        let col = self.col;
+        if let Some(f) = self.stack.last_mut() {
+            if f.count == 1 {
+                f.align_col = Some(col);
+            }
+        }
+        Ok(())
+    }
+
+    fn placed(&mut self) {
+        if let Some(f) = self.stack.last_mut() {
+            // the name doesn't count as an item
+            if f.count > 0 {
+                f.items += 1;
+            }
+            f.count += 1;
         }
     }
 }
@@ -80,44 +680,434 @@ impl Formatter for RulesFormatter {
     where
         W: io::Write,
     {
-        // if first element is string and it has an indent setting
-        if let Some(sexp) = value {
-            if let Sexp::String(ref s) = *sexp {
-                let s: &str = s;
-                if let Some(&i) = self.indent_before.get(s) {
-                    writer.write_all(b"\n")?;
-                    for _ in 0..i {
-                        writer.write_all(&self.indent)?;
-                    }
+        let mut head = self.spare.pop().unwrap_or_default();
+        head.clear();
+        if let Some(Sexp::String(s)) = value {
+            head.push_str(s);
+        }
+        self.heads.push(head);
+        let rule = match self.rules.get(&self.heads) {
+            Some(rule) => *rule,
+            None => self.fallback_rule(),
+        };
+        if rule.newline_before {
+            let level = match rule.indent {
+                Indent::Absolute(n) => n,
+                Indent::Relative(n) => self.stack.last().map(|f| f.indent).unwrap_or(0) + n,
+            };
+            self.newline(writer, level)?;
+        } else if self.separator {
+            let len = 1 + self.heads[self.heads.len() - 1].chars().count();
+            self.separate(writer, true, len)?;
+        }
+        self.separator = false;
+        self.placed();
+        self.stack.push(Frame {
+            rule,
+            indent: self.line_indent,
+            align_col: None,
+            count: 0,
+            items: 0,
+            start_line: self.line,
+        });
+        self.write(writer, "(")
+    }
+
+    fn element<W>(&mut self, _writer: &mut W, _value: &Sexp) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        // the whitespace depends on the rule of a list or the length of a string,
+        // so open() or atom() places it
+        self.separator = true;
+        Ok(())
+    }
+
+    fn close<W>(&mut self, writer: &mut W) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        let frame = match self.stack.pop() {
+            Some(frame) => frame,
+            None => return Err("unbalanced close".into()),
+        };
+        if let Some(head) = self.heads.pop() {
+            self.spare.push(head);
+        }
+        if frame.rule.close_on_own_line && frame.start_line != self.line {
+            self.newline(writer, frame.indent)?;
+        }
+        self.write(writer, ")")
+    }
+
+    fn atom<W>(&mut self, writer: &mut W, text: &str) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        if self.separator {
+            self.separator = false;
+            self.separate(writer, false, text.chars().count())?;
+        }
+        self.write(writer, text)?;
+        self.placed();
+        Ok(())
+    }
+
+    fn set_whitespace(&mut self, indent: Option<&str>, line_ending: Option<LineEnding>) {
+        if let Some(indent) = indent {
+            self.indent = indent.into();
+        }
+        if let Some(line_ending) = line_ending {
+            self.line_ending = line_ending;
+        }
+    }
+}
+
+/// a diff-friendly layout that puts lists on their own lines, so that a
+/// change only touches the lines of the lists that changed
+///
+/// shape:
+///
+/// ```text
+/// (footprint R1
+///   (layer F.Cu)
+///   (fp_poly
+///     (pts (xy 0 0) (xy 1 0) (xy 1 1) (xy 0 1)
+///       (xy 0 2)
+///     )
+///     (width 0.1)
+///   )
+/// )
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffLayout {
+    own_line: Vec<String>,
+    depth: Option<usize>,
+    wrapped: Vec<(String, usize)>,
+}
+
+impl Default for DiffLayout {
+    fn default() -> Self {
+        DiffLayout::new().depth(1).wrap("pts", 4)
+    }
+}
+
+impl DiffLayout {
+    /// a layout without lists on their own lines
+    pub fn new() -> DiffLayout {
+        DiffLayout {
+            own_line: vec![],
+            depth: None,
+            wrapped: vec![],
+        }
+    }
+
+    /// start lists named `name` on their own line
+    pub fn own_line(mut self, name: &str) -> DiffLayout {
+        self.own_line.push(name.into());
+        self
+    }
+
+    /// start every list nested in at least `depth` lists on its own line
+    pub fn depth(mut self, depth: usize) -> DiffLayout {
+        self.depth = Some(depth);
+        self
+    }
+
+    /// start lists named `name`, like `pts`, on their own line and
+    /// put `items` of their elements on each line
+    pub fn wrap(mut self, name: &str, items: usize) -> DiffLayout {
+        self.wrapped.push((name.into(), items));
+        self
+    }
+
+    /// the formatter for the layout
+    pub fn formatter(&self) -> RulesFormatter {
+        let own_line = Rule {
+            newline_before: true,
+            close_on_own_line: true,
+            ..Default::default()
+        };
+        let mut rules = RuleSet::new();
+        for name in &self.own_line {
+            rules.insert(name, own_line);
+        }
+        for &(ref name, items) in &self.wrapped {
+            rules.insert(name, Rule {
+                max_items_per_line: Some(items.max(1)),
+                ..own_line
+            });
+        }
+        let f = RulesFormatter::new(rules).with_default_rule(Rule {
+            close_on_own_line: true,
+            ..Default::default()
+        });
+        match self.depth {
+            Some(depth) => f.with_own_line_depth(depth),
+            None => f,
+        }
+    }
+}
+
+enum Token {
+    Open,
+    Close,
+    // start and end of the string in `PrettyFormatter::text`
+    Text(usize, usize),
+    // a space that becomes a newline when the list is broken
+    Break,
+    // a space that becomes a newline when the list is broken and
+    // the next atom doesn't fit on the line anymore
+    Space,
+}
+
+// tokens of a list for which it isn't known yet if it fits
+struct Pending {
+    start_col: usize,
+    width: usize,
+    nesting: usize,
+    tokens: Vec<Token>,
+    // closing parens of broken parent lists that directly follow the list
+    trailing: usize,
+}
+
+/// Formatter that keeps a list on one line when it fits in a maximum width,
+/// and otherwise puts the lists it contains on their own indented lines
+/// and fills the lines with its atoms
+///
+/// Like Oppen's pretty printer it only buffers as much output as is needed
+/// to decide if a list fits.
+pub struct PrettyFormatter {
+    width: usize,
+    indent: String,
+    line_ending: LineEnding,
+    col: usize,
+    depth: usize,
+    pending: Option<Pending>,
+    queue: VecDeque<Token>,
+    space: bool,
+    // the strings of the tokens that are not printed yet
+    text: String,
+}
+
+impl Default for PrettyFormatter {
+    fn default() -> Self {
+        PrettyFormatter::new(80)
+    }
+}
+
+impl PrettyFormatter {
+    /// create a `PrettyFormatter` for a maximum line width
+    pub fn new(width: usize) -> PrettyFormatter {
+        PrettyFormatter {
+            width,
+            indent: "  ".into(), // two spaces
+            line_ending: LineEnding::Lf,
+            col: 0,
+            depth: 0,
+            pending: None,
+            queue: VecDeque::new(),
+            text: String::new(),
+            space: false,
+        }
+    }
+
+    fn feed<W>(&mut self, writer: &mut W, token: Token) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        self.queue.push_back(token);
+        while let Some(token) = self.queue.pop_front() {
+            match self.pending.take() {
+                Some(pending) => self.buffer(writer, pending, token)?,
+                None => self.print(writer, token)?,
+            }
+        }
+        if self.pending.is_none() {
+            self.text.clear();
+        }
+        Ok(())
+    }
+
+    fn buffer<W>(&mut self, writer: &mut W, mut p: Pending, token: Token) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        if p.nesting == 0 {
+            // the list is complete, only the closing parens of parents that
+            // follow it on the same line still count for its width
+            match token {
+                Token::Close => p.trailing += 1,
+                token => {
+                    self.queue.push_front(token);
+                    return self.flush(writer, p);
                 }
             }
+            p.width += 1;
+        } else {
+            match token {
+                Token::Open => p.nesting += 1,
+                Token::Close => p.nesting -= 1,
+                _ => (),
+            }
+            p.width += match token {
+                Token::Text(a, b) => self.text[a..b].chars().count(),
+                _ => 1,
+            };
+            p.tokens.push(token);
         }
-        writer.write_all(b"(").map_err(From::from)
+        if p.start_col + p.width > self.width {
+            // the list doesn't fit: break it and reconsider what's inside
+            for _ in 0..p.trailing {
+                self.queue.push_front(Token::Close);
+            }
+            let mut tokens = p.tokens.into_iter();
+            tokens.next();
+            for token in tokens.rev() {
+                self.queue.push_front(token);
+            }
+            self.open_broken(writer)?;
+        } else if p.nesting == 0 && p.trailing == self.depth {
+            // the list fits and nothing else can follow it
+            self.flush(writer, p)?;
+        } else {
+            self.pending = Some(p);
+        }
+        Ok(())
     }
 
-    fn element<W>(&mut self, writer: &mut W, value: &Sexp) -> Result<(), SexpError>
+    // print a complete list that fits on one line
+    fn flush<W>(&mut self, writer: &mut W, p: Pending) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        for token in p.tokens {
+            match token {
+                Token::Open => writer.write_all(b"(")?,
+                Token::Close => writer.write_all(b")")?,
+                Token::Text(a, b) => writer.write_all(&self.text.as_bytes()[a..b])?,
+                Token::Break | Token::Space => writer.write_all(b" ")?,
+            }
+        }
+        self.col = p.start_col + p.width - p.trailing;
+        for _ in 0..p.trailing {
+            self.print(writer, Token::Close)?;
+        }
+        Ok(())
+    }
+
+    fn open_broken<W>(&mut self, writer: &mut W) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        writer.write_all(b"(")?;
+        self.col += 1;
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn print<W>(&mut self, writer: &mut W, token: Token) -> Result<(), SexpError>
     where
         W: io::Write,
     {
-        // if containing value is a list and it has indent_before
-        // don't put the space
-        if let Sexp::List(ref l) = *value {
-            if !l.is_empty() {
-                if let Sexp::String(ref s) = l[0] {
-                    let s: &str = s; // why needed?
-                    if self.indent_before.contains_key(s) {
-                        return Ok(());
-                    }
+        if self.space {
+            // fill mode: an atom that doesn't fit goes on the next line,
+            // unless the line only holds the indentation
+            self.space = false;
+            match token {
+                Token::Text(a, b)
+                    if self.col + 1 + self.text[a..b].chars().count() > self.width
+                        && self.col > self.depth * self.indent.len() =>
+                {
+                    self.newline(writer)?
+                }
+                _ => {
+                    writer.write_all(b" ")?;
+                    self.col += 1;
                 }
             }
         }
-        writer.write_all(b" ").map_err(From::from)
+        match token {
+            Token::Open => {
+                self.pending = Some(Pending {
+                    start_col: self.col,
+                    width: 1,
+                    nesting: 1,
+                    tokens: vec![Token::Open],
+                    trailing: 0,
+                });
+            }
+            Token::Close => {
+                writer.write_all(b")")?;
+                self.col += 1;
+                self.depth -= 1;
+            }
+            Token::Text(a, b) => {
+                writer.write_all(&self.text.as_bytes()[a..b])?;
+                self.col += self.text[a..b].chars().count();
+            }
+            Token::Break => self.newline(writer)?,
+            Token::Space => self.space = true,
+        }
+        Ok(())
+    }
+
+    fn newline<W>(&mut self, writer: &mut W) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        writer.write_all(self.line_ending.as_str().as_bytes())?;
+        for _ in 0..self.depth {
+            writer.write_all(self.indent.as_bytes())?;
+        }
+        self.col = self.depth * self.indent.len();
+        Ok(())
+    }
+}
+
+impl Formatter for PrettyFormatter {
+    fn open<W>(&mut self, writer: &mut W, _value: Option<&Sexp>) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        self.feed(writer, Token::Open)
+    }
+
+    fn element<W>(&mut self, writer: &mut W, value: &Sexp) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        let token = if value.is_list() {
+            Token::Break
+        } else {
+            Token::Space
+        };
+        self.feed(writer, token)
     }
 
     fn close<W>(&mut self, writer: &mut W) -> Result<(), SexpError>
     where
         W: io::Write,
     {
-        writer.write_all(b")").map_err(From::from)
+        self.feed(writer, Token::Close)
+    }
+
+    fn atom<W>(&mut self, writer: &mut W, text: &str) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        let start = self.text.len();
+        self.text.push_str(text);
+        let end = self.text.len();
+        self.feed(writer, Token::Text(start, end))
+    }
+
+    fn set_whitespace(&mut self, indent: Option<&str>, line_ending: Option<LineEnding>) {
+        if let Some(indent) = indent {
+            self.indent = indent.into();
+        }
+        if let Some(line_ending) = line_ending {
+            self.line_ending = line_ending;
+        }
     }
 }
@@ -0,0 +1,302 @@
+// (c) 2017 Productize SPRL <joost@productize.be>
+
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use Sexp;
+use error::SexpError;
+use formatter::Rules;
+use iteratom::{FromSexp, IterAtom};
+use parser::{parse_str_at, read_file};
+use scanner::{list_head, Position, Scanner};
+use {parser, ser};
+
+const INDEX_VERSION: i64 = 2;
+
+/// location of a list in the source text
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// name of the list, if it starts with a string
+    pub name: Option<String>,
+    /// byte offset of the opening '('
+    pub start: usize,
+    /// byte offset just after the closing ')'
+    pub end: usize,
+    /// nesting depth, the root list has depth 0
+    pub depth: usize,
+    /// line of the opening '(', starting from 0
+    pub line: usize,
+    /// column of the opening '(' in characters, starting from 0
+    pub col: usize,
+}
+
+/// an index of all lists in a symbolic-expression text
+///
+/// The index can be saved next to a large file so that later runs
+/// can seek to a list and parse only that list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Index {
+    source_len: usize,
+    source_hash: u64,
+    source_file: Option<FileStamp>,
+    entries: Vec<Entry>,
+}
+
+// size and modification time of an indexed file, so that `load_or_build`
+// doesn't have to read and hash a file that didn't change
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FileStamp {
+    len: u64,
+    // nanoseconds since the Unix epoch
+    modified: u64,
+}
+
+impl FileStamp {
+    // the stamp of the file `name`, if its modification time is known and
+    // old enough that a later change can't get the same time
+    fn of(name: &str) -> Result<Option<FileStamp>, SexpError> {
+        let metadata = fs::metadata(name)?;
+        let modified = match metadata.modified() {
+            Ok(modified) => modified,
+            Err(_) => return Ok(None),
+        };
+        // some file systems only keep the time to 2 seconds
+        let settled = SystemTime::now()
+            .duration_since(modified)
+            .map(|age| age > Duration::from_secs(2))
+            .unwrap_or(false);
+        let since_epoch = modified.duration_since(UNIX_EPOCH).ok();
+        let modified = since_epoch.and_then(|t| u64::try_from(t.as_nanos()).ok());
+        Ok(modified.filter(|_| settled).map(|modified| FileStamp {
+            len: metadata.len(),
+            modified,
+        }))
+    }
+}
+
+impl Index {
+    /// build the index of a &str
+    pub fn build(sexp: &str) -> Result<Index, SexpError> {
+        let mut scanner = Scanner::new(sexp);
+        scanner.eat_space();
+        if scanner.peek()? != b'(' {
+            return scanner.parse_error("expected a list");
+        }
+        let mut entries = vec![];
+        scanner.lists(0, &mut |start, end, depth| {
+            let text = &sexp[start.offset..end.offset];
+            entries.push(Entry {
+                name: list_head(text).map(String::from),
+                start: start.offset,
+                end: end.offset,
+                depth,
+                line: start.line,
+                col: start.col,
+            })
+        })?;
+        entries.sort_by_key(|e| e.start);
+        Ok(Index {
+            source_len: sexp.len(),
+            source_hash: hash(sexp.as_bytes()),
+            source_file: None,
+            entries,
+        })
+    }
+
+    /// build the index of a file
+    pub fn build_file(name: &str) -> Result<Index, SexpError> {
+        let s = read_file(name)?;
+        Index::build(&s)
+    }
+
+    /// load the index of `source` from `index`, or build it and save it
+    /// to `index` if it is missing, can't be loaded, or was built from
+    /// a different text than the one in `source` now
+    ///
+    /// When the size and modification time of `source` are the ones saved
+    /// in the index, `source` isn't read at all.
+    pub fn load_or_build(source: &str, index: &str) -> Result<Index, SexpError> {
+        // before reading, so a change while reading gives a different stamp
+        let stamp = FileStamp::of(source)?;
+        let loaded = if Path::new(index).exists() {
+            Index::load(index).ok()
+        } else {
+            None
+        };
+        if let Some(ref i) = loaded {
+            if stamp.is_some() && i.source_file == stamp {
+                return Ok(i.clone());
+            }
+        }
+        let s = read_file(source)?;
+        let (mut i, valid) = match loaded {
+            Some(i) if i.source_len == s.len() && i.source_hash == hash(s.as_bytes()) => (i, true),
+            _ => (Index::build(&s)?, false),
+        };
+        if !valid || i.source_file != stamp {
+            i.source_file = stamp;
+            i.save(index)?;
+        }
+        Ok(i)
+    }
+
+    /// size in bytes of the indexed text
+    pub fn source_len(&self) -> usize {
+        self.source_len
+    }
+
+    /// hash of the indexed text, to notice changes that keep its size
+    pub fn source_hash(&self) -> u64 {
+        self.source_hash
+    }
+
+    /// all lists in the order they appear in the text
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// all lists named `name` in the order they appear in the text
+    ///
+    /// shape: (name ...)
+    pub fn named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Entry> {
+        self.entries
+            .iter()
+            .filter(move |e| e.name.as_ref().map(|n| n == name).unwrap_or(false))
+    }
+
+    /// convert the index to a symbolic-expression
+    pub fn to_sexp(&self) -> Sexp {
+        let mut v = vec![
+            "sexp_index".into(),
+            ("version", &INDEX_VERSION).into(),
+            ("source_len", &self.source_len).into(),
+            ("source_hash", &format!("{:016x}", self.source_hash)).into(),
+        ];
+        if let Some(stamp) = self.source_file {
+            let mut l = Sexp::start("source_file");
+            l.push(stamp.len as i64);
+            l.push(stamp.modified as i64);
+            v.push(l);
+        }
+        for e in &self.entries {
+            let mut l = Sexp::start("list");
+            l.push(e.start as i64);
+            l.push(e.end as i64);
+            l.push(e.depth as i64);
+            l.push(e.line as i64);
+            l.push(e.col as i64);
+            if let Some(ref name) = e.name {
+                l.push(name);
+            }
+            v.push(l);
+        }
+        Sexp::List(v)
+    }
+
+    /// save the index to a file
+    pub fn save(&self, name: &str) -> Result<(), SexpError> {
+        let mut rules = Rules::new();
+        rules.insert("list", 1);
+        let s = ser::to_string_with_rules(&self.to_sexp(), rules)?;
+        let mut f = File::create(name)?;
+        f.write_all(s.as_bytes())?;
+        Ok(())
+    }
+
+    /// load an index saved with `save`
+    pub fn load(name: &str) -> Result<Index, SexpError> {
+        let s = parser::parse_file(name)?;
+        Index::from_sexp(&s)
+    }
+}
+
+impl FromSexp for Index {
+    fn from_sexp(s: &Sexp) -> Result<Index, SexpError> {
+        let mut i = IterAtom::new(s, "sexp_index")?;
+        let version = i.i_in_list("version")?;
+        if version != INDEX_VERSION {
+            return Err(format!("unsupported index version {}", version).into());
+        }
+        let source_len = i.i_in_list("source_len")? as usize;
+        let source_hash = i.s_in_list("source_hash")?;
+        let source_hash = u64::from_str_radix(&source_hash, 16)
+            .map_err(|_| format!("invalid source hash {}", source_hash))?;
+        let source_file = i.maybe_t();
+        let entries = i.vec()?;
+        Ok(Index {
+            source_len,
+            source_hash,
+            source_file,
+            entries,
+        })
+    }
+}
+
+impl FromSexp for FileStamp {
+    fn from_sexp(s: &Sexp) -> Result<FileStamp, SexpError> {
+        let mut i = IterAtom::new(s, "source_file")?;
+        let len = u64::try_from(i.i("len")?);
+        let modified = u64::try_from(i.i("modified")?);
+        match (len, modified) {
+            (Ok(len), Ok(modified)) => i.close(FileStamp { len, modified }),
+            _ => Err(format!("negative value in {}", s).into()),
+        }
+    }
+}
+
+impl FromSexp for Entry {
+    fn from_sexp(s: &Sexp) -> Result<Entry, SexpError> {
+        let mut i = IterAtom::new(s, "list")?;
+        let start = i.i("start")? as usize;
+        let end = i.i("end")? as usize;
+        let depth = i.i("depth")? as usize;
+        let line = i.i("line")? as usize;
+        let col = i.i("col")? as usize;
+        let name = i.maybe_s();
+        i.close(Entry {
+            name,
+            start,
+            end,
+            depth,
+            line,
+            col,
+        })
+    }
+}
+
+impl Entry {
+    /// seek to the list in `reader` and parse only that list
+    pub fn parse<R>(&self, reader: &mut R) -> Result<Sexp, SexpError>
+    where
+        R: Read + Seek,
+    {
+        reader.seek(SeekFrom::Start(self.start as u64))?;
+        let mut buf = vec![0; self.end - self.start];
+        reader.read_exact(&mut buf)?;
+        let s = String::from_utf8(buf)?;
+        let start = Position {
+            offset: self.start,
+            line: self.line,
+            col: self.col,
+        };
+        parse_str_at(&s, start)
+    }
+
+    /// open the indexed file and parse only this list
+    pub fn parse_file(&self, name: &str) -> Result<Sexp, SexpError> {
+        let mut f = File::open(name)?;
+        self.parse(&mut f)
+    }
+}
+
+// 64 bit FNV-1a, which unlike the hasher of the standard library
+// stays the same between Rust versions
+fn hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |h, &c| {
+        (h ^ u64::from(c)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
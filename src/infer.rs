@@ -0,0 +1,326 @@
+// (c) 2017 Productize SPRL <joost@productize.be>
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use error::SexpError;
+use formatter::{Context, Indent, LineEnding, Rule, RuleSet};
+use scanner::{Event, Scanner};
+use ser::QuotingPolicy;
+use style::Style;
+
+// in order of preference when several policies match equally well
+const POLICIES: &[QuotingPolicy] = &[
+    QuotingPolicy::Default,
+    QuotingPolicy::KicadLegacy,
+    QuotingPolicy::KicadV6,
+    QuotingPolicy::Kicad,
+    QuotingPolicy::Minimal,
+    QuotingPolicy::Lisp,
+    QuotingPolicy::Always,
+];
+
+enum Child {
+    Atom { new_line: bool },
+    List(usize),
+}
+
+// a list as it was found in the text
+struct List<'a> {
+    parent: Option<usize>,
+    head: String,
+    // the whitespace before the list when it starts a line
+    leading: Option<&'a str>,
+    // the whitespace at the start of the line the list starts on
+    line_leading: &'a str,
+    line: usize,
+    count: usize,
+    multi_line: bool,
+    close_on_own_line: bool,
+    // elements after the name
+    children: Vec<Child>,
+}
+
+#[derive(Default)]
+struct Stats {
+    new_line: usize,
+    inline: usize,
+    relative: HashMap<usize, usize>,
+    absolute: HashMap<usize, usize>,
+    close_own_line: usize,
+    close_inline: usize,
+    items: HashMap<usize, usize>,
+}
+
+impl Stats {
+    fn add(&mut self, other: &Stats) {
+        self.new_line += other.new_line;
+        self.inline += other.inline;
+        self.close_own_line += other.close_own_line;
+        self.close_inline += other.close_inline;
+        for (k, v) in &other.relative {
+            *self.relative.entry(*k).or_insert(0) += v;
+        }
+        for (k, v) in &other.absolute {
+            *self.absolute.entry(*k).or_insert(0) += v;
+        }
+        for (k, v) in &other.items {
+            *self.items.entry(*k).or_insert(0) += v;
+        }
+    }
+
+    fn rule(&self) -> Rule {
+        let mut rule = Rule::default();
+        if self.new_line > self.inline {
+            rule.newline_before = true;
+            let relative = most_common(&self.relative);
+            let absolute = most_common(&self.absolute);
+            rule.indent = match (relative, absolute) {
+                (Some((r, rn)), Some((_, an))) if rn >= an => Indent::Relative(r),
+                (_, Some((a, _))) => Indent::Absolute(a),
+                _ => rule.indent,
+            };
+        }
+        rule.close_on_own_line = self.close_own_line > self.close_inline;
+        rule.max_items_per_line = most_common(&self.items).map(|(n, _)| n);
+        rule
+    }
+}
+
+// the most common value, preferring the smallest one on a tie
+fn most_common<K: Copy + Ord + Hash>(counts: &HashMap<K, usize>) -> Option<(K, usize)> {
+    let mut v: Vec<_> = counts.iter().map(|(k, n)| (*k, *n)).collect();
+    v.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    v.first().cloned()
+}
+
+fn leading(s: &str) -> &str {
+    let l = s.bytes().take_while(|&c| c == b' ' || c == b'\t').count();
+    &s[..l]
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// derive the style of a text
+pub fn infer(text: &str) -> Result<Style, SexpError> {
+    let mut lists: Vec<List> = vec![];
+    let mut stack: Vec<usize> = vec![];
+    let mut mismatches = vec![0; POLICIES.len()];
+    let mut scanner = Scanner::new(text);
+    scanner.eat_space();
+    if scanner.eof() {
+        return Ok(Style::default());
+    }
+    // the heads of the lists in `stack`, as `Context` wants them
+    let mut heads: Vec<&str> = vec![];
+    // the line the previous token ended on, and the whitespace at the start
+    // of that line; a token starts a line when it isn't on that line
+    let mut last_line = None;
+    let mut line_leading = "";
+    scanner.walk(&mut |event| match event {
+        Event::Open(p) => {
+            let index = lists.len();
+            let parent = stack.last().cloned();
+            let starts_line = last_line != Some(p.line);
+            // the text before a token that starts a line is blank ASCII,
+            // so its characters are bytes
+            let prefix = &text[p.offset - p.col..p.offset];
+            if starts_line {
+                line_leading = leading(prefix);
+            }
+            lists.push(List {
+                parent,
+                head: String::new(),
+                leading: if parent.is_some() && starts_line {
+                    Some(prefix)
+                } else {
+                    None
+                },
+                line_leading,
+                line: p.line,
+                count: 0,
+                multi_line: false,
+                close_on_own_line: false,
+                children: vec![],
+            });
+            if let Some(parent) = parent {
+                let parent = &mut lists[parent];
+                if parent.count > 0 {
+                    parent.children.push(Child::List(index));
+                }
+                parent.count += 1;
+            }
+            stack.push(index);
+            heads.push("");
+            last_line = Some(p.line);
+        }
+        Event::Atom(p, raw) => {
+            let starts_line = last_line != Some(p.line);
+            if starts_line {
+                line_leading = leading(&text[p.offset - p.col..p.offset]);
+            }
+            let quoted = raw.starts_with('"');
+            let value = if quoted && raw.len() > 1 {
+                &raw[1..raw.len() - 1]
+            } else {
+                raw
+            };
+            let mut index = 0;
+            if let Some(&top) = stack.last() {
+                let list = &mut lists[top];
+                index = list.count;
+                if index == 0 {
+                    list.head = value.into();
+                    *heads.last_mut().unwrap() = value;
+                } else {
+                    list.children.push(Child::Atom { new_line: starts_line });
+                }
+                list.count += 1;
+            }
+            let context = Context::new(&heads, index, index + 1);
+            for (policy, n) in POLICIES.iter().zip(mismatches.iter_mut()) {
+                if policy.quote(&context, value) != quoted {
+                    *n += 1;
+                }
+            }
+            // quoted strings may go on over several lines
+            last_line = Some(p.line);
+            if let Some(i) = raw.rfind('\n') {
+                last_line = Some(p.line + raw.matches('\n').count());
+                line_leading = leading(&raw[i + 1..]);
+            }
+        }
+        Event::Close(p) => {
+            if let Some(index) = stack.pop() {
+                let list = &mut lists[index];
+                list.multi_line = p.line != list.line;
+                list.close_on_own_line = last_line != Some(p.line);
+            }
+            heads.pop();
+            last_line = Some(p.line);
+        }
+    })?;
+
+    let indent = indent_unit(&lists);
+    let level = |s: &str| -> usize {
+        if indent == "\t" {
+            s.bytes().filter(|&c| c == b'\t').count()
+        } else {
+            s.len() / indent.len()
+        }
+    };
+
+    // where lists start
+    let mut stats: HashMap<(Option<String>, String), Stats> = HashMap::new();
+    for list in &lists {
+        let parent = list.parent.map(|p| &lists[p]);
+        let s = stats
+            .entry((parent.map(|p| p.head.clone()), list.head.clone()))
+            .or_default();
+        if let Some(parent) = parent {
+            match list.leading {
+                Some(leading) => {
+                    s.new_line += 1;
+                    let (l, pl) = (level(leading), level(parent.line_leading));
+                    if l >= pl {
+                        *s.relative.entry(l - pl).or_insert(0) += 1;
+                    }
+                    *s.absolute.entry(l).or_insert(0) += 1;
+                }
+                None => s.inline += 1,
+            }
+        }
+        if list.multi_line {
+            if list.close_on_own_line {
+                s.close_own_line += 1;
+            } else {
+                s.close_inline += 1;
+            }
+        }
+    }
+
+    // lists that continue on a new line after a number of elements
+    let new_line_rules: HashMap<(Option<String>, String), bool> = stats
+        .iter()
+        .map(|(k, s)| (k.clone(), s.new_line > s.inline))
+        .collect();
+    for list in &lists {
+        let mut items = 0;
+        for child in &list.children {
+            let (new_line, forced) = match *child {
+                Child::Atom { new_line } => (new_line, false),
+                Child::List(i) => {
+                    let key = (Some(list.head.clone()), lists[i].head.clone());
+                    (lists[i].leading.is_some(), new_line_rules[&key])
+                }
+            };
+            if new_line && !forced && items > 0 {
+                let key = (list.parent.map(|p| lists[p].head.clone()), list.head.clone());
+                if let Some(s) = stats.get_mut(&key) {
+                    *s.items.entry(items).or_insert(0) += 1;
+                }
+                break;
+            }
+            items = if new_line { 1 } else { items + 1 };
+        }
+    }
+
+    let mut by_head: HashMap<String, Stats> = HashMap::new();
+    for ((_, head), s) in &stats {
+        by_head.entry(head.clone()).or_default().add(s);
+    }
+    let mut rules = RuleSet::new();
+    let default = Rule::default();
+    for (head, s) in &by_head {
+        let rule = s.rule();
+        if rule != default {
+            rules.insert(head, rule);
+        }
+    }
+    for ((parent, head), s) in &stats {
+        if let Some(ref parent) = *parent {
+            let rule = s.rule();
+            if rule != by_head[head].rule() {
+                rules.insert_path(&[parent, head], rule);
+            }
+        }
+    }
+
+    let best = (0..POLICIES.len()).min_by_key(|&i| mismatches[i]).unwrap_or(0);
+    let crlf = text.matches("\r\n").count();
+    Ok(Style {
+        rules,
+        indent: Some(indent),
+        line_ending: if crlf * 2 > text.matches('\n').count() {
+            LineEnding::CrLf
+        } else {
+            LineEnding::Lf
+        },
+        final_newline: text.ends_with('\n'),
+        quoting: POLICIES[best],
+        ..Default::default()
+    })
+}
+
+// tabs when any line is indented with a tab, otherwise the largest
+// number of spaces all indentations are a multiple of
+fn indent_unit(lists: &[List]) -> String {
+    let leading = lists.iter().filter_map(|l| l.leading).filter(|l| !l.is_empty());
+    let mut spaces = 0;
+    for l in leading {
+        if l.contains('\t') {
+            return "\t".into();
+        }
+        spaces = gcd(spaces, l.len());
+    }
+    if spaces == 0 {
+        spaces = 2;
+    }
+    " ".repeat(spaces)
+}
@@ -12,7 +12,7 @@ where
     Self: Sized,
 {
     /// convert from a symbolic-expression to something
-    fn from_sexp(&Sexp) -> Result<Self, SexpError>;
+    fn from_sexp(s: &Sexp) -> Result<Self, SexpError>;
 }
 
 /// convert from a symbolic-expression to something (dispatcher)
@@ -70,42 +70,42 @@ impl<'a> IterAtom<'a> {
     ///
     /// shape: (... ... 42 ...)
     pub fn i(&mut self, name: &str) -> Result<i64, SexpError> {
-        self.expect(name, |x| x.i().map_err(From::from))
+        self.expect(name, |x| x.i())
     }
 
     /// expect a float while iterating a `Sexp` list
     ///
     /// shape: (... ... 42.7 ...)
     pub fn f(&mut self, name: &str) -> Result<f64, SexpError> {
-        self.expect(name, |x| x.f().map_err(From::from))
+        self.expect(name, |x| x.f())
     }
 
     /// expect a String while iterating a `Sexp` list
     ///
     /// shape: (... ... hello ...)
     pub fn s(&mut self, name: &str) -> Result<String, SexpError> {
-        self.expect(name, |x| x.s().map_err(From::from))
+        self.expect(name, |x| x.s())
     }
 
     /// expect a list contained String while iterating a `Sexp` list
     ///
     /// shape: (... ... hello ...)
     pub fn s_in_list(&mut self, name: &str) -> Result<String, SexpError> {
-        self.expect(name, |x| x.named_value_s(name).map_err(From::from))
+        self.expect(name, |x| x.named_value_s(name))
     }
 
     /// expect a list contained i64 while iterating a `Sexp` list
     ///
     /// shape: (... ... (name 42) ...)
     pub fn i_in_list(&mut self, name: &str) -> Result<i64, SexpError> {
-        self.expect(name, |x| x.named_value_i(name).map_err(From::from))
+        self.expect(name, |x| x.named_value_i(name))
     }
 
     /// expect a list contained f64 while iterating a `Sexp` list
     ///
     /// shape: (... ... (name 42.7) ...)
     pub fn f_in_list(&mut self, name: &str) -> Result<f64, SexpError> {
-        self.expect(name, |x| x.named_value_f(name).map_err(From::from))
+        self.expect(name, |x| x.named_value_f(name))
     }
 
 
@@ -148,10 +148,7 @@ impl<'a> IterAtom<'a> {
     {
         let res = match self.iter.peek() {
             None => None,
-            Some(s) => match convert(s) {
-                Ok(t) => Some(t),
-                Err(_) => None,
-            },
+            Some(s) => convert(s).ok(),
         };
         match res {
             Some(x) => {
@@ -0,0 +1,487 @@
+// (c) 2017 Productize SPRL <joost@productize.be>
+
+use std::io;
+
+use Sexp;
+use error::SexpError;
+use formatter::{Formatter, LineEnding};
+use sexp;
+
+/// generations of the KiCad file formats
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KicadVersion {
+    /// KiCad 5 and older (`module`): two space indentation and
+    /// strings are only quoted when needed
+    Legacy,
+    /// KiCad 6 and 7 footprints and schematics: two space indentation,
+    /// the lists that describe an item on the line of the item and user
+    /// strings always quoted, uuids not
+    V6,
+    /// KiCad 8 and newer (`footprint`, `kicad_pcb`, `kicad_sch`): tab
+    /// indentation, every list on its own line and user strings always
+    /// quoted
+    Modern,
+}
+
+// KiCad 8 `Prettify` constants
+const XY_COLUMN_LIMIT: usize = 99;
+const TOKEN_WRAP_COLUMN: usize = 72;
+
+// lists of KiCad 5 footprints that stay on the line of their parent
+const LEGACY_MODULE_INLINE: &[&str] = &["layer", "tedit", "tstamp"];
+// lists of KiCad 5 model placements that are put on their own line
+const LEGACY_MODEL_OWN_LINE: &[&str] = &["at", "offset", "scale", "rotate"];
+// optional pad parameters that KiCad 5 puts on a second line
+const LEGACY_PAD_EXTRA: &[&str] = &[
+    "die_length",
+    "solder_mask_margin",
+    "solder_paste_margin",
+    "solder_paste_margin_ratio",
+    "clearance",
+    "zone_connect",
+    "thermal_width",
+    "thermal_gap",
+];
+
+// items of KiCad 6 and 7 that keep their uuid or tstamp on their line
+const V6_ID_INLINE: &[&str] = &[
+    "no_connect",
+    "pad",
+    "fp_line",
+    "fp_rect",
+    "fp_circle",
+    "fp_arc",
+    "fp_poly",
+    "fp_curve",
+    "segment",
+    "via",
+];
+// footprint graphics, which KiCad 7 continues on a second line at `stroke`
+const V6_FP_SHAPES: &[&str] = &["fp_line", "fp_rect", "fp_circle", "fp_arc", "fp_poly", "fp_curve"];
+// what the units of a KiCad 6 and 7 symbol are drawn with
+const V6_SYMBOL_ITEMS: &[&str] = &["rectangle", "polyline", "circle", "arc", "text", "bezier"];
+// pad parameters that KiCad 6 and 7 put on a second line before the extras
+const V6_PAD_NET: &[&str] = &["net", "pinfunction", "pintype"];
+const V6_TITLE_BLOCK: &[&str] = &["title", "date", "rev", "company", "comment"];
+
+fn legacy_own_line(parent: Option<&str>, head: &str) -> bool {
+    match parent {
+        Some("fp_text") | Some("gr_text") => head == "effects",
+        Some("model") => LEGACY_MODEL_OWN_LINE.contains(&head),
+        _ => false,
+    }
+}
+
+// does a list named `head` in a list named `parent` start a line in the
+// KiCad 6 and 7 layout
+fn v6_own_line(grandparent: Option<&str>, parent: &str, head: &str) -> bool {
+    match head {
+        "effects" => parent != "name" && parent != "number",
+        // pins of placed symbols keep their uuid on their line
+        "uuid" | "tstamp" => {
+            !(V6_ID_INLINE.contains(&parent) || parent == "pin" && grandparent == Some("symbol"))
+        }
+        "stroke" | "fill" => !V6_FP_SHAPES.contains(&parent),
+        "property" => parent != "pad",
+        "instances" | "project" | "path" => true,
+        // the fields of a placed symbol in `symbol_instances` or `instances`
+        "reference" => parent == "path",
+        "symbol" => parent == "lib_symbols" || parent == "symbol",
+        "pin" => parent == "symbol" || parent == "sheet",
+        "name" | "number" => parent == "pin",
+        // the flags of a placed symbol, but not of a library symbol
+        "in_bom" => parent == "symbol" && grandparent == Some("kicad_sch"),
+        "pts" => (parent == "polyline" || parent == "bezier") && grandparent == Some("symbol"),
+        _ if parent == "model" => LEGACY_MODEL_OWN_LINE.contains(&head),
+        _ if parent == "symbol" => V6_SYMBOL_ITEMS.contains(&head),
+        _ if parent == "title_block" => V6_TITLE_BLOCK.contains(&head),
+        _ => false,
+    }
+}
+
+// the number of empty lines KiCad 6 and 7 put before a list named `head`
+// in the root of a schematic, after a list named `previous`
+fn v6_empty_lines(previous: &str, head: &str) -> usize {
+    match (previous, head) {
+        ("", _) | ("uuid", _) | ("paper", _) | ("title_block", _) => 1,
+        ("lib_symbols", "junction") => 1,
+        ("lib_symbols", _) => 2,
+        (_, "junction") => 0,
+        (_, "symbol") | (_, "sheet") | (_, "sheet_instances") | (_, "symbol_instances") => 1,
+        _ if previous == head => 0,
+        _ => 1,
+    }
+}
+
+struct List {
+    head: Option<String>,
+    // the list starts a line
+    own_line: bool,
+    // number of `xy` lists written so far
+    xy: usize,
+    // legacy and KiCad 6: the closing ')' goes on its own line
+    close_on_own_line: bool,
+    // legacy and KiCad 6: a pad extra parameter was written
+    pad_extra: bool,
+}
+
+/// Formatter that reproduces the layout of files written by KiCad
+///
+/// To also get KiCad's quoting of strings and the newline KiCad ends files
+/// with, use `ser::to_string_kicad`.
+pub struct KicadFormatter {
+    version: KicadVersion,
+    indent: String,
+    line_ending: LineEnding,
+    stack: Vec<List>,
+    // names of closed lists, reused for the next lists
+    spare: Vec<String>,
+    column: usize,
+    last_close: bool,
+    in_xy: bool,
+    multi_line: bool,
+    // KiCad 6: the name of the previous list in the root
+    previous: String,
+}
+
+impl KicadFormatter {
+    /// create a `KicadFormatter` for a KiCad file format generation
+    pub fn new(version: KicadVersion) -> KicadFormatter {
+        let indent = match version {
+            KicadVersion::Legacy | KicadVersion::V6 => "  ",
+            KicadVersion::Modern => "\t",
+        };
+        KicadFormatter {
+            version,
+            indent: indent.into(),
+            line_ending: LineEnding::Lf,
+            stack: vec![],
+            spare: vec![],
+            column: 0,
+            last_close: false,
+            in_xy: false,
+            multi_line: false,
+            previous: String::new(),
+        }
+    }
+
+    fn write<W>(&mut self, writer: &mut W, s: &str) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        writer.write_all(s.as_bytes())?;
+        self.column += s.len();
+        Ok(())
+    }
+
+    fn newline<W>(&mut self, writer: &mut W, level: usize) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        writer.write_all(self.line_ending.as_str().as_bytes())?;
+        self.column = 0;
+        for _ in 0..level {
+            writer.write_all(self.indent.as_bytes())?;
+            self.column += self.indent.len();
+        }
+        Ok(())
+    }
+
+    fn head(&self, up: usize) -> Option<&str> {
+        let l = self.stack.len();
+        if up >= l {
+            return None;
+        }
+        self.stack[l - 1 - up].head.as_ref().map(|s| &s[..])
+    }
+
+    // place the '(' of a list in the KiCad 8 layout
+    fn open_modern<W>(&mut self, writer: &mut W, head: Option<&str>) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        let is_xy = head == Some("xy");
+        let depth = self.stack.len();
+        if depth == 0 {
+            self.write(writer, "(")?;
+        } else if self.in_xy && is_xy && self.column < XY_COLUMN_LIMIT {
+            self.write(writer, " (")?;
+        } else {
+            self.newline(writer, depth)?;
+            self.write(writer, "(")?;
+        }
+        self.in_xy = is_xy;
+        Ok(())
+    }
+
+    // place the '(' of a list in the KiCad 5 layout
+    fn open_legacy<W>(&mut self, writer: &mut W, head: Option<&str>) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        let depth = self.stack.len();
+        let head = head.unwrap_or("");
+        let parent = self.head(0);
+        if depth == 1 && !LEGACY_MODULE_INLINE.contains(&head) {
+            self.newline(writer, 1)?;
+        } else if depth == 2 && legacy_own_line(parent, head) {
+            self.newline(writer, 2)?;
+            self.stack[1].close_on_own_line = true;
+        } else if depth == 2 && parent == Some("pad") && LEGACY_PAD_EXTRA.contains(&head) {
+            self.pad_extra(writer)?;
+        } else if head == "xy" && parent == Some("pts") {
+            // KiCad 5 starts a new line every 4 points
+            let xy = self.stack[depth - 1].xy;
+            if xy > 0 && xy.is_multiple_of(4) {
+                self.newline(writer, depth - 1)?;
+            } else {
+                self.write(writer, " ")?;
+            }
+            self.stack[depth - 1].xy += 1;
+        } else if depth > 0 {
+            self.write(writer, " ")?;
+        }
+        self.write(writer, "(")
+    }
+
+    // place the '(' of a list in the KiCad 6 and 7 layout and tell if it
+    // starts a line
+    fn open_v6<W>(&mut self, writer: &mut W, head: Option<&str>) -> Result<bool, SexpError>
+    where
+        W: io::Write,
+    {
+        let depth = self.stack.len();
+        let head = head.unwrap_or("");
+        if depth == 0 {
+            self.previous.clear();
+            self.write(writer, "(")?;
+            return Ok(false);
+        }
+        let parent = self.head(0).unwrap_or("");
+        let grandparent = self.head(1);
+        let mut own_line = false;
+        if depth == 1 {
+            if head == "version" || head == "generator" {
+                self.write(writer, " (")?;
+                return Ok(false);
+            }
+            if parent == "kicad_sch" {
+                for _ in 0..v6_empty_lines(&self.previous, head) {
+                    writer.write_all(self.line_ending.as_str().as_bytes())?;
+                }
+            }
+            self.previous.clear();
+            self.previous.push_str(head);
+            own_line = true;
+        } else if head == "stroke" && V6_FP_SHAPES.contains(&parent) {
+            // the rest of the shape follows on the same line
+            self.newline(writer, depth)?;
+        } else if parent == "pad"
+            && (V6_PAD_NET.contains(&head) || LEGACY_PAD_EXTRA.contains(&head))
+        {
+            self.pad_extra(writer)?;
+        } else if head == "xy" && parent == "pts" {
+            own_line = self.stack[depth - 1].own_line;
+            if !own_line {
+                self.write(writer, " ")?;
+            }
+        } else if v6_own_line(grandparent, parent, head) {
+            own_line = true;
+        } else {
+            self.write(writer, " ")?;
+        }
+        if own_line {
+            self.newline(writer, depth)?;
+            self.stack[depth - 1].close_on_own_line = true;
+        }
+        self.write(writer, "(")?;
+        Ok(own_line)
+    }
+
+    // the extra parameters of a pad go on a second line
+    fn pad_extra<W>(&mut self, writer: &mut W) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        let depth = self.stack.len();
+        if self.stack[depth - 1].pad_extra {
+            self.write(writer, " ")
+        } else {
+            self.stack[depth - 1].pad_extra = true;
+            self.newline(writer, depth)
+        }
+    }
+}
+
+impl Formatter for KicadFormatter {
+    fn open<W>(&mut self, writer: &mut W, value: Option<&Sexp>) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        let head = match value {
+            Some(Sexp::String(s)) => Some(&s[..]),
+            _ => None,
+        };
+        let own_line = match self.version {
+            KicadVersion::Modern => {
+                self.open_modern(writer, head)?;
+                false
+            }
+            KicadVersion::Legacy => {
+                self.open_legacy(writer, head)?;
+                false
+            }
+            KicadVersion::V6 => self.open_v6(writer, head)?,
+        };
+        let head = head.map(|s| {
+            let mut head = self.spare.pop().unwrap_or_default();
+            head.clear();
+            head.push_str(s);
+            head
+        });
+        self.stack.push(List {
+            head,
+            own_line,
+            xy: 0,
+            close_on_own_line: false,
+            pad_extra: false,
+        });
+        self.last_close = false;
+        Ok(())
+    }
+
+    fn element<W>(&mut self, writer: &mut W, value: &Sexp) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        // lists place their own whitespace when they are opened
+        if value.is_list() {
+            return Ok(());
+        }
+        match self.version {
+            KicadVersion::Modern => {
+                if self.in_xy || self.column < TOKEN_WRAP_COLUMN {
+                    self.write(writer, " ")
+                } else {
+                    let depth = self.stack.len();
+                    self.newline(writer, depth)?;
+                    self.multi_line = true;
+                    Ok(())
+                }
+            }
+            KicadVersion::Legacy | KicadVersion::V6 => self.write(writer, " "),
+        }
+    }
+
+    fn close<W>(&mut self, writer: &mut W) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        let list = match self.stack.pop() {
+            Some(list) => list,
+            None => return Err("unbalanced close".into()),
+        };
+        if let Some(head) = list.head {
+            self.spare.push(head);
+        }
+        let depth = self.stack.len();
+        match self.version {
+            KicadVersion::Modern => {
+                if self.last_close || self.multi_line {
+                    self.newline(writer, depth)?;
+                    self.multi_line = false;
+                }
+            }
+            KicadVersion::Legacy | KicadVersion::V6 => {
+                if depth == 0 || list.close_on_own_line {
+                    self.newline(writer, depth)?;
+                }
+            }
+        }
+        self.write(writer, ")")?;
+        self.last_close = true;
+        Ok(())
+    }
+
+    fn atom<W>(&mut self, writer: &mut W, text: &str) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        self.last_close = false;
+        self.write(writer, text)
+    }
+
+    fn set_whitespace(&mut self, indent: Option<&str>, line_ending: Option<LineEnding>) {
+        if let Some(indent) = indent {
+            self.indent = indent.into();
+        }
+        if let Some(line_ending) = line_ending {
+            self.line_ending = line_ending;
+        }
+    }
+}
+
+// does KiCad's `OUTPUTFORMATTER::Quotes` quote a string
+pub(crate) fn needs_quotes(s: &str) -> bool {
+    s.is_empty() || s.starts_with('#') || s.starts_with('"')
+        || s.contains(['\t', ' ', '(', ')', '\n', '\r'])
+}
+
+// KiCad 6 and 7 quote the same fields as KiCad 8, but not the generator,
+// uuids and the members of groups, and also the values of `symbol_instances`
+pub(crate) fn always_quoted_v6(heads: &[&str], index: usize) -> bool {
+    let l = heads.len();
+    let parent = if l > 1 { heads[l - 2] } else { "" };
+    match heads.last() {
+        Some(&"generator") | Some(&"uuid") | Some(&"tstamp") => false,
+        Some(&"members") if parent == "group" => false,
+        Some(&"value") if parent == "path" => index == 1,
+        _ => always_quoted(heads, index),
+    }
+}
+
+// KiCad 8 and newer always quote these fields; `heads` are the names of the
+// containing lists, innermost last, and `index` is the position in the list
+pub(crate) fn always_quoted(heads: &[&str], index: usize) -> bool {
+    let l = heads.len();
+    if index == 0 || l == 0 {
+        return false;
+    }
+    let head = heads[l - 1];
+    let parent = if l > 1 { heads[l - 2] } else { "" };
+    let grandparent = if l > 2 { heads[l - 3] } else { "" };
+    match head {
+        "layers" | "members" => index >= 1,
+        "property" => index == 1 || index == 2,
+        "fp_text" | "net" | "comment" => index == 2,
+        "pin" => index == 1 && parent == "symbol" && grandparent == "kicad_sch",
+        "type" | "color" | "material" => index == 1 && parent == "layer",
+        "generator" | "generator_version" | "paper" | "title" | "date" | "rev"
+        | "company" | "layer" | "uuid" | "tstamp" | "footprint" | "descr" | "tags"
+        | "pad" | "model" | "net_name" | "pintype" | "pinfunction" | "group" | "gr_text"
+        | "fp_text_box" | "gr_text_box" | "face" | "lib_id" | "symbol" | "name"
+        | "number" | "project" | "path" | "reference" | "page" | "text" | "label"
+        | "global_label" | "hierarchical_label" | "sheetname" | "sheetfile"
+        | "outputdirectory" | "copper_finish" => index == 1,
+        // layer definitions like (0 "F.Cu" signal "Front")
+        _ if parent == "layers" && head.parse::<u32>().is_ok() => index == 1 || index == 3,
+        _ => false,
+    }
+}
+
+/// encode a string the way KiCad writes it at position `index`
+/// of a list nested in lists named `heads`, innermost last
+pub fn encode_string(version: KicadVersion, heads: &[&str], index: usize, s: &str) -> String {
+    let quote = match version {
+        KicadVersion::Legacy => needs_quotes(s),
+        KicadVersion::V6 => needs_quotes(s) || always_quoted_v6(heads, index),
+        KicadVersion::Modern => needs_quotes(s) || always_quoted(heads, index),
+    };
+    if quote {
+        let mut buf = String::with_capacity(s.len() + 2);
+        sexp::push_quoted(&mut buf, s);
+        buf
+    } else {
+        String::from(s)
+    }
+}
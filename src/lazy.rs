@@ -0,0 +1,152 @@
+// (c) 2017 Productize SPRL <joost@productize.be>
+
+use std::cell::OnceCell;
+use std::slice::Iter;
+
+use Sexp;
+use error::SexpError;
+use parser::parse_str_at;
+use scanner::{list_head, Position, Scanner};
+
+/// a list of which the elements are located in the source text
+/// but only parsed into a `Sexp` the first time they are accessed
+pub struct LazyList<'a> {
+    elements: Vec<LazyElement<'a>>,
+}
+
+/// an element of a `LazyList`
+pub struct LazyElement<'a> {
+    text: &'a str,
+    start: Position,
+    value: OnceCell<Sexp>,
+}
+
+/// parse a &str lazily
+///
+/// Only the matching parenthesis of the root list are searched for,
+/// the elements of the root list are parsed on first access.
+pub fn parse_str(sexp: &str) -> Result<LazyList<'_>, SexpError> {
+    let mut scanner = Scanner::new(sexp);
+    scanner.eat_space();
+    if scanner.peek()? != b'(' {
+        return scanner.parse_error("expected a list");
+    }
+    scanner.eat_char(b'(')?;
+    let mut elements = vec![];
+    scanner.list_elements(|start, end| {
+        elements.push(LazyElement {
+            text: &sexp[start.offset..end.offset],
+            start,
+            value: OnceCell::new(),
+        })
+    })?;
+    scanner.eat_char(b')')?;
+    Ok(LazyList { elements })
+}
+
+impl<'a> LazyList<'a> {
+    /// number of elements in the list
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    /// is the list empty
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    /// the name of the list, if it starts with a string
+    ///
+    /// shape: (name ...)
+    pub fn name(&self) -> Option<&'a str> {
+        match self.elements.first() {
+            Some(e) if e.text.starts_with('"') => Some(&e.text[1..e.text.len() - 1]),
+            Some(e) if !e.is_list() => Some(e.text),
+            _ => None,
+        }
+    }
+
+    /// access an element by index
+    pub fn get(&self, index: usize) -> Option<&LazyElement<'a>> {
+        self.elements.get(index)
+    }
+
+    /// iterate over the elements
+    pub fn iter(&self) -> Iter<'_, LazyElement<'a>> {
+        self.elements.iter()
+    }
+
+    /// first element that is a list named `name`
+    ///
+    /// shape: (... (name ...) ...)
+    pub fn find(&self, name: &str) -> Option<&LazyElement<'a>> {
+        self.elements.iter().find(|e| e.name() == Some(name))
+    }
+
+    /// all elements that are a list named `name`
+    ///
+    /// shape: (... (name ...) (name ...) ...)
+    pub fn find_all<'b>(&'b self, name: &'b str) -> impl Iterator<Item = &'b LazyElement<'a>> {
+        self.elements.iter().filter(move |e| e.name() == Some(name))
+    }
+
+    /// parse all remaining elements and build the complete `Sexp`
+    pub fn into_sexp(self) -> Result<Sexp, SexpError> {
+        let mut v = Vec::with_capacity(self.elements.len());
+        for e in self.elements {
+            v.push(e.into_sexp()?);
+        }
+        Ok(Sexp::List(v))
+    }
+}
+
+impl<'a, 'b> IntoIterator for &'b LazyList<'a> {
+    type Item = &'b LazyElement<'a>;
+    type IntoIter = Iter<'b, LazyElement<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.elements.iter()
+    }
+}
+
+impl<'a> LazyElement<'a> {
+    /// the unparsed source text of the element
+    pub fn text(&self) -> &'a str {
+        self.text
+    }
+
+    /// is this element a list
+    pub fn is_list(&self) -> bool {
+        self.text.starts_with('(')
+    }
+
+    /// the name of the element if it is a list starting with a string,
+    /// found without parsing the element
+    ///
+    /// shape: (name ...)
+    pub fn name(&self) -> Option<&'a str> {
+        list_head(self.text)
+    }
+
+    /// has the element already been parsed
+    pub fn is_parsed(&self) -> bool {
+        self.value.get().is_some()
+    }
+
+    /// access the element, parsing it on first access
+    pub fn sexp(&self) -> Result<&Sexp, SexpError> {
+        if let Some(s) = self.value.get() {
+            return Ok(s);
+        }
+        let s = parse_str_at(self.text, self.start)?;
+        Ok(self.value.get_or_init(|| s))
+    }
+
+    /// take the element, parsing it if that didn't happen yet
+    pub fn into_sexp(self) -> Result<Sexp, SexpError> {
+        match self.value.into_inner() {
+            Some(s) => Ok(s),
+            None => parse_str_at(self.text, self.start),
+        }
+    }
+}
@@ -4,11 +4,24 @@
 
 #![warn(missing_docs)]
 
+extern crate memchr;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "mmap")]
+extern crate memmap2;
+#[cfg(feature = "gzip")]
+extern crate flate2;
+#[cfg(feature = "zstd")]
+extern crate zstd;
+
 pub use error::*;
 pub use sexp::*;
 
+mod compress;
 mod error;
 mod formatter;
+mod infer;
+mod scanner;
 mod sexp;
 
 /// symbolic-expression parser code: data -> symbolic-expression
@@ -20,8 +33,35 @@ pub mod ser;
 /// high-level API for deconstructing symbolic-expressions
 pub mod iteratom;
 
+/// lazy parsing: elements are only parsed when accessed
+pub mod lazy;
+
+/// offset index for random access into large symbolic-expression files
+pub mod index;
+
+/// formatting that matches the files written by KiCad
+pub mod kicad;
+
+/// controlled formatting of floating point numbers
+pub mod float;
+
+/// formatting styles that can be kept in a configuration file
+pub mod style;
+
+/// mapping from serialized text back to the nodes of a symbolic-expression
+pub mod sourcemap;
+
+/// ANSI syntax highlighting for terminals
+pub mod color;
+
+/// editing symbolic-expression text while keeping its layout
+pub mod edit;
+
 pub use formatter::Rules;
-pub use formatter::Formatter;
+pub use formatter::{Indent, Rule, RuleSet};
+pub use formatter::{Formatter, LineEnding, Sink};
+pub use formatter::{Adapter, Context, ContextFormatter};
+pub use formatter::{CompactFormatter, DiffLayout, PrettyFormatter, RulesFormatter};
 
 pub use iteratom::from_sexp;
 
@@ -1,166 +1,197 @@
 // (c) 2016-2017 Productize SPRL <joost@productize.be>
 
+use compress;
 use error::SexpError;
-use Sexp;
+use {Bytes, Sexp};
 use parse_error;
-use std::io;
+use scanner::{Position, Scanner};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
+use std::str;
+use std::io::{self, BufReader};
 use std::fs::File;
 use std::io::prelude::*;
 
-#[derive(Default)]
-struct Parser {
-    data: Vec<char>,
-    position: usize,
-    line: usize,
-    line_position: usize,
+/// parse a &str to a symbolic-expression
+pub fn parse_str(sexp: &str) -> Result<Sexp, SexpError> {
+    if sexp.is_empty() {
+        return Ok(Sexp::default());
+    }
+    parse(&mut Scanner::new(sexp), )
 }
 
-impl Parser {
-    fn peek(&self) -> Result<char, SexpError> {
-        self.fail_on_eof()?;
-        Ok(self.data[self.position])
-    }
+/// parse a &str that was cut out of a larger text at `start`,
+/// so that errors report the position in the larger text
+pub(crate) fn parse_str_at(sexp: &str, start: Position) -> Result<Sexp, SexpError> {
+    let start = Position { offset: 0, ..start };
+    parse(&mut Scanner::at(sexp, start), )
+}
 
-    fn get(&mut self) -> Result<char, SexpError> {
-        self.fail_on_eof()?;
-        let c = self.data[self.position];
-        self.position += 1;
-        self.line_position += 1;
-        if c == '\n' {
-            self.line += 1;
-            self.line_position = 0;
-        }
-        Ok(c)
+/// parse a &str to a symbolic-expression, parsing the elements
+/// of the root list on multiple threads
+///
+/// The result is identical to the one of `parse_str`.
+#[cfg(feature = "rayon")]
+pub fn parse_str_parallel(sexp: &str) -> Result<Sexp, SexpError> {
+    let mut scanner = Scanner::new(sexp);
+    scanner.eat_space();
+    if scanner.eof() || scanner.peek()? != b'(' {
+        return parse_str(sexp);
     }
+    scanner.eat_char(b'(')?;
+    let mut elements = vec![];
+    scanner.list_elements(|start, end| elements.push((start, end.offset)))?;
+    scanner.eat_char(b')')?;
+    let v = elements
+        .par_iter()
+        .map(|&(start, end)| parse_str_at(&sexp[start.offset..end], start))
+        .collect::<Result<Vec<Sexp>, SexpError>>()?;
+    Ok(Sexp::List(v))
+}
 
-    fn inc(&mut self) {
-        let c = self.data[self.position];
-        self.position += 1;
-        self.line_position += 1;
-        if c == '\n' {
-            self.line += 1;
-            self.line_position = 0;
-        }
+fn parse(scanner: &mut Scanner) -> Result<Sexp, SexpError> {
+    scanner.eat_space();
+    match scanner.peek()? {
+        b'(' => parse_list(scanner),
+        b'"' => Ok(Sexp::String(scanner.quoted_string()?.into())),
+        b')' => scanner.parse_error("Unexpected )"),
+        _ => Ok(Sexp::String(scanner.bare_string().into())),
     }
+}
 
-    fn eat_space(&mut self) {
-        while !self.eof() {
-            let c = self.data[self.position];
-            if c == ' ' || c == '\t' {
-                self.inc();
-                continue;
-            }
-            break;
-        }
+fn parse_list(scanner: &mut Scanner) -> Result<Sexp, SexpError> {
+    scanner.eat_char(b'(')?;
+    let mut v = vec![];
+    scanner.eat_whitespace();
+    while !scanner.eof() && scanner.peek()? != b')' {
+        v.push(parse(scanner)?);
+        scanner.eat_whitespace();
     }
+    scanner.eat_char(b')')?;
+    scanner.eat_space();
+    Ok(Sexp::List(v))
+}
 
-    fn eat_char(&mut self, c: char) -> Result<(), SexpError> {
-        let c2 = self.get()?;
-        if c != c2 {
-            self.parse_error(&format!("expected {} got {}", c, c2))
-        } else {
-            Ok(())
-        }
+/// parse a canonical symbolic-expression, where every string is prefixed
+/// with its length, like `(3:abc[10:text/plain]5:hello)`
+///
+/// Strings that are UTF-8 and have no display hint become `Sexp::String`,
+/// the others `Sexp::Bytes`. As the data doesn't have lines, errors report
+/// the byte offset, counting from 1, as the column of line 1. Lists nested
+/// deeper than `MAX_CANONICAL_DEPTH` are an error.
+pub fn parse_canonical(data: &[u8]) -> Result<Sexp, SexpError> {
+    if data.is_empty() {
+        return Ok(Sexp::default());
     }
-
-    fn eof(&self) -> bool {
-        self.position >= self.data.len()
+    let mut parser = CanonicalParser { data, position: 0 };
+    let e = parser.element(0)?;
+    if parser.position < data.len() {
+        return parser.parse_error("Trailing data after the root");
     }
+    Ok(e)
+}
 
-    fn fail_on_eof(&self) -> Result<(), SexpError> {
-        if self.eof() {
-            return self.parse_error("End of file reached");
-        }
-        Ok(())
-    }
+/// how deep lists in a canonical symbolic-expression may be nested, so
+/// that parsing and writing them can't run out of stack
+pub const MAX_CANONICAL_DEPTH: usize = 256;
 
-    fn parse_error<T>(&self, msg: &str) -> Result<T, SexpError> {
-        parse_error(self.line + 1, self.line_position + 1, msg.to_string())
-    }
+struct CanonicalParser<'a> {
+    data: &'a [u8],
+    position: usize,
 }
 
-/// parse a &str to a symbolic-expression
-pub fn parse_str(sexp: &str) -> Result<Sexp, SexpError> {
-    if sexp.is_empty() {
-        return Ok(Sexp::default());
+impl<'a> CanonicalParser<'a> {
+    fn peek(&self) -> Result<u8, SexpError> {
+        match self.data.get(self.position) {
+            Some(&c) => Ok(c),
+            None => self.parse_error("End of file reached"),
+        }
     }
-    let mut parser = Parser::default();
-    parser.data = sexp.chars().collect();
-    parse(&mut parser)
-}
 
-fn parse(parser: &mut Parser) -> Result<Sexp, SexpError> {
-    parser.eat_space();
-    let c = parser.peek()?;
-    if c == '(' {
-        parse_list(parser)
-    } else if c == '"' {
-        parse_quoted_string(parser)
-    } else if c == ')' {
-        parser.parse_error("Unexpected )")
-    } else {
-        parse_bare_string(parser)
+    fn eat_char(&mut self, c: u8) -> Result<(), SexpError> {
+        if self.peek()? != c {
+            return self.parse_error(&format!("expected {}", c as char));
+        }
+        self.position += 1;
+        Ok(())
     }
-}
 
-fn parse_list(parser: &mut Parser) -> Result<Sexp, SexpError> {
-    parser.eat_char('(')?;
-    let mut v = vec![];
-    while !parser.eof() {
-        let c = parser.peek()?;
-        if c == ')' {
-            break;
-        } else if c == ' ' || c == '\t' || c == '\r' || c == '\n' {
-            parser.inc()
-        } else {
-            let s = parse(parser)?;
-            v.push(s)
+    // `depth` is the number of lists around the element
+    fn element(&mut self, depth: usize) -> Result<Sexp, SexpError> {
+        match self.peek()? {
+            b'(' => {
+                if depth == MAX_CANONICAL_DEPTH {
+                    return self.parse_error("Lists nested too deep");
+                }
+                self.position += 1;
+                let mut v = vec![];
+                while self.peek()? != b')' {
+                    v.push(self.element(depth + 1)?);
+                }
+                self.position += 1;
+                Ok(Sexp::List(v))
+            }
+            b')' => self.parse_error("Unexpected )"),
+            b'[' => {
+                self.position += 1;
+                let hint = self.string()?.to_vec();
+                self.eat_char(b']')?;
+                let data = self.string()?.to_vec();
+                Ok(Bytes {
+                    hint: Some(hint),
+                    data,
+                }.into())
+            }
+            _ => {
+                let data = self.string()?;
+                Ok(match str::from_utf8(data) {
+                    Ok(s) => Sexp::String(s.into()),
+                    Err(_) => Bytes {
+                        hint: None,
+                        data: data.to_vec(),
+                    }.into(),
+                })
+            }
         }
     }
-    parser.eat_char(')')?;
-    parser.eat_space();
-    Ok(Sexp::List(v))
-}
 
-fn parse_quoted_string(parser: &mut Parser) -> Result<Sexp, SexpError> {
-    let mut s = String::new();
-    parser.eat_char('"')?;
-    // note that escaped quotes are actually not allowed
-    let mut escape = false;
-    while !parser.eof() {
-        let c = parser.peek()?;
-        if c == '\\' {
-            escape = true;
-        } else if c == '"' {
-            if !escape {
-                break;
-            } else {
-                escape = false;
+    // a decimal length without leading zeros, ':' and that many bytes
+    fn string(&mut self) -> Result<&'a [u8], SexpError> {
+        let start = self.position;
+        while self.peek()?.is_ascii_digit() {
+            self.position += 1;
+        }
+        let digits = &self.data[start..self.position];
+        if digits.is_empty() {
+            return self.parse_error("expected the length of a string");
+        }
+        if digits.len() > 1 && digits[0] == b'0' {
+            return self.parse_error("length with a leading zero");
+        }
+        // only ASCII digits
+        let len = str::from_utf8(digits).ok().and_then(|d| d.parse::<usize>().ok());
+        self.eat_char(b':')?;
+        match len {
+            Some(len) if len <= self.data.len() - self.position => {
+                let s = &self.data[self.position..self.position + len];
+                self.position += len;
+                Ok(s)
+            }
+            _ => {
+                self.position = self.data.len();
+                self.parse_error("End of file reached")
             }
-        } else {
-            escape = false;
         }
-        s.push(c);
-        parser.inc()
     }
-    parser.eat_char('"')?;
-    Ok(Sexp::String(s))
-}
 
-fn parse_bare_string(parser: &mut Parser) -> Result<Sexp, SexpError> {
-    let mut s = String::new();
-    while !parser.eof() {
-        let c = parser.peek()?;
-        if c == ' ' || c == '(' || c == ')' || c == '\r' || c == '\n' {
-            break;
-        }
-        s.push(c);
-        parser.inc()
+    fn parse_error<T>(&self, msg: &str) -> Result<T, SexpError> {
+        parse_error(1, self.position + 1, msg.to_string())
     }
-    Ok(Sexp::String(s))
 }
 
-fn read_file(name: &str) -> Result<String, io::Error> {
+pub(crate) fn read_file(name: &str) -> Result<String, io::Error> {
     let mut f = File::open(name)?;
     let mut s = String::new();
     f.read_to_string(&mut s)?;
@@ -168,7 +199,55 @@ fn read_file(name: &str) -> Result<String, io::Error> {
 }
 
 /// parse a file as a symbolic-expression
+///
+/// gzip and zstd compressed files are recognized by their magic bytes
+/// and decompressed when the `gzip` or `zstd` feature is enabled
 pub fn parse_file(name: &str) -> Result<Sexp, SexpError> {
-    let s = read_file(name)?;
+    let f = BufReader::new(File::open(name)?);
+    let mut s = String::new();
+    compress::Reader::new(f)?.read_to_string(&mut s)?;
     parse_str(&s[..])
 }
+
+/// a read-only memory-mapped file
+///
+/// Use `as_str` to hand the mapped text to `parse_str`, `lazy::parse_str`
+/// or `index::Index::build` without copying it into a `String` first.
+#[cfg(feature = "mmap")]
+pub struct MappedFile {
+    map: Option<Mmap>,
+}
+
+#[cfg(feature = "mmap")]
+impl MappedFile {
+    /// memory-map a file and check that it is valid UTF-8
+    pub fn open(name: &str) -> Result<MappedFile, SexpError> {
+        let f = File::open(name)?;
+        // mapping an empty file fails on some platforms
+        if f.metadata()?.len() == 0 {
+            return Ok(MappedFile { map: None });
+        }
+        // the file is only read; modifying it while it is mapped is
+        // undefined behavior, like for any memory-mapped file
+        let map = unsafe { Mmap::map(&f)? };
+        str::from_utf8(&map)?;
+        Ok(MappedFile { map: Some(map) })
+    }
+
+    /// the contents of the file
+    pub fn as_str(&self) -> &str {
+        match self.map {
+            // checked to be valid UTF-8 in `open`
+            Some(ref map) => unsafe { str::from_utf8_unchecked(map) },
+            None => "",
+        }
+    }
+}
+
+/// parse a file as a symbolic-expression, reading it through
+/// a memory mapping instead of copying it into a `String`
+#[cfg(feature = "mmap")]
+pub fn parse_file_mmap(name: &str) -> Result<Sexp, SexpError> {
+    let f = MappedFile::open(name)?;
+    parse_str(f.as_str())
+}
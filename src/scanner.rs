@@ -0,0 +1,358 @@
+// (c) 2017 Productize SPRL <joost@productize.be>
+
+use memchr::{memchr, memchr_iter, memrchr};
+
+use error::SexpError;
+use parse_error;
+
+// classes of bytes, so that the scanner finds the end of a run of bytes
+// and counts the columns it skips in one pass
+const BLANK: u8 = 1;
+const LINE: u8 = 2;
+const END: u8 = 4;
+const CONTINUATION: u8 = 8;
+
+static CLASSES: [u8; 256] = classes();
+
+const fn classes() -> [u8; 256] {
+    let mut classes = [0; 256];
+    classes[b' ' as usize] = BLANK | END;
+    classes[b'\t' as usize] = BLANK;
+    classes[b'\r' as usize] = LINE | END;
+    classes[b'\n' as usize] = LINE | END;
+    classes[b'(' as usize] = END;
+    classes[b')' as usize] = END;
+    // only the first byte of an UTF-8 sequence counts as a column
+    let mut c = 0x80;
+    while c < 0xC0 {
+        classes[c] = CONTINUATION;
+        c += 1;
+    }
+    classes
+}
+
+/// a location in the source text
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Position {
+    /// byte offset
+    pub offset: usize,
+    /// line, starting from 0
+    pub line: usize,
+    /// column in characters, starting from 0
+    pub col: usize,
+}
+
+/// what a `Scanner` finds while walking over a symbolic-expression
+pub enum Event<'a> {
+    /// the '(' of a list
+    Open(Position),
+    /// a string, including its quotes if it is quoted
+    Atom(Position, &'a str),
+    /// the position just after the ')' of a list
+    Close(Position),
+}
+
+/// byte-level scanner over symbolic-expressions, used by the parser and
+/// to walk over symbolic-expressions without building them
+pub struct Scanner<'a> {
+    text: &'a str,
+    data: &'a [u8],
+    position: usize,
+    line: usize,
+    line_position: usize,
+}
+
+impl<'a> Scanner<'a> {
+    pub fn new(data: &'a str) -> Scanner<'a> {
+        Scanner::at(data, Position::default())
+    }
+
+    /// create a scanner for `data` that starts at `start`
+    pub fn at(data: &'a str, start: Position) -> Scanner<'a> {
+        Scanner {
+            text: data,
+            data: data.as_bytes(),
+            position: start.offset,
+            line: start.line,
+            line_position: start.col,
+        }
+    }
+
+    pub fn position(&self) -> Position {
+        Position {
+            offset: self.position,
+            line: self.line,
+            col: self.line_position,
+        }
+    }
+
+    pub fn eof(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    pub fn peek(&self) -> Result<u8, SexpError> {
+        if self.eof() {
+            return self.parse_error("End of file reached");
+        }
+        Ok(self.data[self.position])
+    }
+
+    pub fn inc(&mut self) {
+        let c = self.data[self.position];
+        self.position += 1;
+        // only count the first byte of an UTF-8 sequence as a column
+        if c & 0xC0 != 0x80 {
+            self.line_position += 1;
+        }
+        if c == b'\n' {
+            self.line += 1;
+            self.line_position = 0;
+        }
+    }
+
+    // move to the byte at `end`, counting the lines and columns skipped
+    fn advance(&mut self, end: usize) {
+        let skipped = &self.data[self.position..end];
+        match memrchr(b'\n', skipped) {
+            Some(last) => {
+                self.line += memchr_iter(b'\n', skipped).count();
+                self.line_position = count_chars(&skipped[last + 1..]);
+            }
+            None => self.line_position += count_chars(skipped),
+        }
+        self.position = end;
+    }
+
+    pub fn eat_char(&mut self, c: u8) -> Result<(), SexpError> {
+        let c2 = self.peek()?;
+        if c != c2 {
+            // the scanner only stops on character boundaries
+            let found = self.text[self.position..].chars().next().unwrap_or('?');
+            self.inc();
+            return self.parse_error(&format!("expected {} got {}", c as char, found));
+        }
+        self.inc();
+        Ok(())
+    }
+
+    /// skip spaces and tabs
+    pub fn eat_space(&mut self) {
+        let start = self.position;
+        let mut end = start;
+        while end < self.data.len() && CLASSES[self.data[end] as usize] & BLANK != 0 {
+            end += 1;
+        }
+        self.line_position += end - start;
+        self.position = end;
+    }
+
+    /// skip spaces, tabs and line endings
+    pub fn eat_whitespace(&mut self) {
+        while let Some(&c) = self.data.get(self.position) {
+            if CLASSES[c as usize] & (BLANK | LINE) == 0 {
+                break;
+            }
+            self.position += 1;
+            if c == b'\n' {
+                self.line += 1;
+                self.line_position = 0;
+            } else {
+                self.line_position += 1;
+            }
+        }
+    }
+
+    /// skip the elements of a list up to, but not including, the closing ')'
+    /// calling `element` with the start and end of each direct element
+    pub fn list_elements<F>(&mut self, mut element: F) -> Result<(), SexpError>
+    where
+        F: FnMut(Position, Position),
+    {
+        while !self.eof() {
+            let c = self.data[self.position];
+            if c == b')' {
+                break;
+            } else if is_whitespace(c) {
+                self.eat_whitespace()
+            } else {
+                let start = self.position();
+                self.skip_element()?;
+                element(start, self.position());
+                self.eat_space();
+            }
+        }
+        Ok(())
+    }
+
+    /// skip an element that is known not to start with whitespace,
+    /// without eating the whitespace after it
+    fn skip_element(&mut self) -> Result<(), SexpError> {
+        match self.peek()? {
+            b'(' => self.lists(0, &mut |_, _, _| ()),
+            b'"' => self.quoted_string().map(|_| ()),
+            _ => {
+                self.bare_string();
+                Ok(())
+            }
+        }
+    }
+
+    /// skip the list starting at the current '(', calling `visit` with
+    /// the start, end and depth of that list and of every nested list
+    ///
+    /// nested lists are visited before the list containing them
+    pub fn lists<F>(&mut self, depth: usize, visit: &mut F) -> Result<(), SexpError>
+    where
+        F: FnMut(Position, Position, usize),
+    {
+        let start = self.position();
+        self.eat_char(b'(')?;
+        while !self.eof() {
+            let c = self.data[self.position];
+            if c == b')' {
+                break;
+            } else if is_whitespace(c) {
+                self.eat_whitespace()
+            } else if c == b'(' {
+                self.lists(depth + 1, visit)?;
+                self.eat_space();
+            } else {
+                self.skip_element()?;
+            }
+        }
+        self.eat_char(b')')?;
+        visit(start, self.position(), depth);
+        Ok(())
+    }
+
+    /// walk over the element at the current position, calling `visit`
+    /// for every list and string in the order they appear
+    pub fn walk<F>(&mut self, visit: &mut F) -> Result<(), SexpError>
+    where
+        F: FnMut(Event<'a>),
+    {
+        let start = self.position();
+        match self.peek()? {
+            b'(' => {
+                self.eat_char(b'(')?;
+                visit(Event::Open(start));
+                while !self.eof() {
+                    let c = self.data[self.position];
+                    if c == b')' {
+                        break;
+                    } else if is_whitespace(c) {
+                        self.eat_whitespace()
+                    } else {
+                        self.walk(visit)?;
+                    }
+                }
+                self.eat_char(b')')?;
+                visit(Event::Close(self.position()));
+            }
+            b'"' => {
+                self.quoted_string()?;
+                visit(Event::Atom(start, &self.text[start.offset..self.position]));
+            }
+            _ => {
+                self.bare_string();
+                visit(Event::Atom(start, &self.text[start.offset..self.position]));
+            }
+        }
+        Ok(())
+    }
+
+    /// the quoted string at the current '"', without its quotes
+    pub fn quoted_string(&mut self) -> Result<&'a str, SexpError> {
+        self.eat_char(b'"')?;
+        let start = self.position;
+        // a '"' right after a '\\' doesn't end the string
+        let mut end = self.data.len();
+        let mut from = start;
+        while let Some(i) = memchr(b'"', &self.data[from..]) {
+            if from + i > start && self.data[from + i - 1] == b'\\' {
+                from += i + 1;
+            } else {
+                end = from + i;
+                break;
+            }
+        }
+        self.advance(end);
+        self.eat_char(b'"')?;
+        Ok(&self.text[start..end])
+    }
+
+    /// the string that isn't quoted at the current position
+    pub fn bare_string(&mut self) -> &'a str {
+        let start = self.position;
+        let mut end = start;
+        let mut chars = 0;
+        // it ends before a line ending, so only the column changes
+        while let Some(&c) = self.data.get(end) {
+            let class = CLASSES[c as usize];
+            if class & END != 0 {
+                break;
+            }
+            if class & CONTINUATION == 0 {
+                chars += 1;
+            }
+            end += 1;
+        }
+        self.line_position += chars;
+        self.position = end;
+        &self.text[start..end]
+    }
+
+    pub fn parse_error<T>(&self, msg: &str) -> Result<T, SexpError> {
+        parse_error(self.line + 1, self.line_position + 1, msg.to_string())
+    }
+}
+
+// the number of characters in UTF-8 `s`
+fn count_chars(s: &[u8]) -> usize {
+    s.iter().filter(|&&c| c & 0xC0 != 0x80).count()
+}
+
+fn is_whitespace(c: u8) -> bool {
+    c == b' ' || c == b'\t' || c == b'\r' || c == b'\n'
+}
+
+/// the head of the list in `text` if it starts with a string
+///
+/// `text` has to be a complete list as located by the `Scanner`
+pub fn list_head(text: &str) -> Option<&str> {
+    let bytes = text.as_bytes();
+    if bytes.first() != Some(&b'(') {
+        return None;
+    }
+    let mut i = 1;
+    while i < bytes.len() && is_whitespace(bytes[i]) {
+        i += 1;
+    }
+    let start = i;
+    match bytes.get(i) {
+        None | Some(&b'(') | Some(&b')') => None,
+        Some(&b'"') => {
+            let mut escape = false;
+            i += 1;
+            while i < bytes.len() {
+                let c = bytes[i];
+                if c == b'"' && !escape {
+                    break;
+                }
+                escape = c == b'\\';
+                i += 1;
+            }
+            Some(&text[start + 1..i])
+        }
+        Some(_) => {
+            while i < bytes.len() {
+                let c = bytes[i];
+                if c == b' ' || c == b'(' || c == b')' || c == b'\r' || c == b'\n' {
+                    break;
+                }
+                i += 1;
+            }
+            Some(&text[start..i])
+        }
+    }
+}
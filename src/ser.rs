@@ -1,73 +1,409 @@
 // (c) 2016-2017 Productize SPRL <joost@productize.be>
 
+use std::fmt;
+use std::mem;
+use std::str;
 use std::io;
+use std::io::BufWriter;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use formatter::*;
 
 use Sexp;
+use compress::{Compression, Writer};
 use error::SexpError;
-use encode_string;
+use sexp;
+use float::FloatFormat;
+use kicad::{self, KicadFormatter, KicadVersion};
+use parser;
+use sourcemap::{Recorder, Recording, SourceMap};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+use style::Style;
 
-struct Serializer<W, F = CompactFormatter> {
+/// how strings are quoted when they are serialized
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum QuotingPolicy {
+    /// the rules of `encode_string`, as used by `Display`
+    #[default]
+    Default,
+    /// only quote strings that wouldn't be parsed back as the same string;
+    /// a '"' that isn't escaped yet is written as `\"` in a quoted string
+    Minimal,
+    /// quote every string
+    Always,
+    /// quote like KiCad 5 and older
+    KicadLegacy,
+    /// quote like KiCad 6 and 7, which always quote some fields but not uuids
+    KicadV6,
+    /// quote like KiCad 8 and newer, which always quote some fields
+    Kicad,
+    /// quote strings that aren't valid Lisp symbols
+    Lisp,
+}
+
+impl QuotingPolicy {
+    /// should the string `s` at `context` be quoted
+    pub fn quote(&self, context: &Context, s: &str) -> bool {
+        match *self {
+            QuotingPolicy::Default => sexp::needs_quotes(s),
+            QuotingPolicy::Minimal => {
+                s.is_empty() || s.starts_with('"') || s.contains([' ', '\t', '\r', '\n', '(', ')'])
+            }
+            QuotingPolicy::Always => true,
+            QuotingPolicy::KicadLegacy => kicad::needs_quotes(s),
+            QuotingPolicy::KicadV6 => {
+                kicad::needs_quotes(s) || kicad::always_quoted_v6(context.heads(), context.index())
+            }
+            QuotingPolicy::Kicad => {
+                kicad::needs_quotes(s) || kicad::always_quoted(context.heads(), context.index())
+            }
+            QuotingPolicy::Lisp => {
+                s.is_empty() || s.contains(char::is_whitespace)
+                    || s.contains(['(', ')', '"', ';', '\'', '`', ',', '|'])
+            }
+        }
+    }
+}
+
+type QuoteFn = dyn Fn(&Context, &str) -> Option<bool> + Send + Sync;
+
+/// strings that are written in a `FieldFormat`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    /// the string at a position in the lists with a name,
+    /// like `Field::Index("uuid", 1)` for the value of `(uuid 1234)`
+    Index(String, usize),
+    /// the strings after the name of the lists with a name inside lists
+    /// with another name, like `Field::Child("pad", "at")` for `1 2` in
+    /// `(pad 1 smd (at 1 2))`
+    Child(String, String),
+}
+
+impl Field {
+    /// the string at `index` in lists named `parent`
+    pub fn index(parent: &str, index: usize) -> Field {
+        Field::Index(parent.into(), index)
+    }
+
+    /// the strings of lists named `child` inside lists named `parent`
+    pub fn child(parent: &str, child: &str) -> Field {
+        Field::Child(parent.into(), child.into())
+    }
+
+    fn matches(&self, context: &Context) -> bool {
+        let heads = context.heads();
+        let l = heads.len();
+        match *self {
+            Field::Index(ref parent, index) => {
+                l > 0 && heads[l - 1] == parent && context.index() == index
+            }
+            Field::Child(ref parent, ref child) => {
+                l > 1 && heads[l - 2] == parent && heads[l - 1] == child && context.index() > 0
+            }
+        }
+    }
+}
+
+/// how the strings of a `Field` are written; what is `None` is left to
+/// the other options
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FieldFormat {
+    /// quote the strings, or leave them bare
+    pub quote: Option<bool>,
+    /// write the strings that are numbers, including integers, in a format
+    pub number: Option<FloatFormat>,
+}
+
+impl FieldFormat {
+    /// always quote the strings
+    pub fn quoted() -> FieldFormat {
+        FieldFormat {
+            quote: Some(true),
+            number: None,
+        }
+    }
+
+    /// write the numbers in `format`
+    pub fn number(format: FloatFormat) -> FieldFormat {
+        FieldFormat {
+            quote: None,
+            number: Some(format),
+        }
+    }
+}
+
+/// options for the serialization of a symbolic-expression
+#[derive(Clone, Default)]
+pub struct Options {
+    quoting: QuotingPolicy,
+    quote_with: Option<Arc<QuoteFn>>,
+    floats: Option<FloatFormat>,
+    fields: Vec<(Field, FieldFormat)>,
+    indent: Option<String>,
+    line_ending: Option<LineEnding>,
+    final_newline: bool,
+    backup: bool,
+}
+
+impl Options {
+    /// the default options
+    pub fn new() -> Options {
+        Options::default()
+    }
+
+    /// select how strings are quoted
+    pub fn quoting(mut self, policy: QuotingPolicy) -> Options {
+        self.quoting = policy;
+        self
+    }
+
+    /// override the quoting policy: `f` is called for every string and
+    /// returns `Some(true)` to quote it, `Some(false)` to leave it bare,
+    /// or `None` to let the quoting policy decide
+    pub fn quote_with<F>(mut self, f: F) -> Options
+    where
+        F: Fn(&Context, &str) -> Option<bool> + Send + Sync + 'static,
+    {
+        self.quote_with = Some(Arc::new(f));
+        self
+    }
+
+    /// write strings that are decimal numbers with a fraction or an
+    /// exponent with a sign, like `0.30000000000000004` or `1e-7`, in
+    /// `format`; strings that are quoted are left alone, use `field` with
+    /// `FieldFormat::number` to also reformat those
+    pub fn floats(mut self, format: FloatFormat) -> Options {
+        self.floats = Some(format);
+        self
+    }
+
+    /// write the strings of `field` in `format`, before the quoting
+    /// policy, `quote_with` and `floats`; when fields overlap the last
+    /// one added is used
+    pub fn field(mut self, field: Field, format: FieldFormat) -> Options {
+        self.fields.push((field, format));
+        self
+    }
+
+    /// indent with `indent` instead of what the formatter uses by default
+    pub fn indent(mut self, indent: &str) -> Options {
+        self.indent = Some(indent.into());
+        self
+    }
+
+    /// end lines with `line_ending`
+    pub fn line_ending(mut self, line_ending: LineEnding) -> Options {
+        self.line_ending = Some(line_ending);
+        self
+    }
+
+    /// end the output with a line ending
+    pub fn final_newline(mut self, final_newline: bool) -> Options {
+        self.final_newline = final_newline;
+        self
+    }
+
+    /// keep the previous version of a file as `name.bak` when writing to a file
+    pub fn backup(mut self, backup: bool) -> Options {
+        self.backup = backup;
+        self
+    }
+
+    // pass the indentation and line ending to `formatter`
+    fn set_whitespace<F: ContextFormatter>(&self, formatter: &mut F) {
+        if self.indent.is_some() || self.line_ending.is_some() {
+            formatter.set_whitespace(self.indent.as_ref().map(|s| &s[..]), self.line_ending);
+        }
+    }
+
+    // the final newline after a root `value`
+    fn write_final_newline<W: Sink>(&self, writer: &mut W) -> Result<(), SexpError> {
+        if self.final_newline {
+            let line_ending = self.line_ending.unwrap_or_default();
+            writer.write_str(line_ending.as_str())?;
+        }
+        Ok(())
+    }
+
+    // the text of the string `s` at `context`; when it differs from `s`
+    // it is put in `buf`, which is reused to not allocate for every string
+    fn encode_string<'b>(&self, context: &Context, s: &'b str, buf: &'b mut String) -> &'b str {
+        let field = self.fields
+            .iter()
+            .rev()
+            .find(|&(field, _)| field.matches(context))
+            .map(|&(_, format)| format)
+            .unwrap_or_default();
+        let quote = field.quote
+            .or_else(|| self.quote_with.as_ref().and_then(|f| f(context, s)))
+            .unwrap_or_else(|| self.quoting.quote(context, s));
+        // quoted strings like `(generator_version "8.0")` aren't numbers
+        let reformatted = match field.number {
+            Some(f) => f.reformat_number(s),
+            None if !quote => self.floats.and_then(|f| f.reformat(s)),
+            None => None,
+        };
+        let value = reformatted.as_ref().map(|s| &s[..]).unwrap_or(s);
+        if !quote && reformatted.is_none() {
+            return s;
+        }
+        buf.clear();
+        if quote {
+            sexp::push_quoted(buf, value);
+        } else {
+            buf.push_str(value);
+        }
+        buf
+    }
+}
+
+// the output of `write` as a String, to which the formatters write directly
+fn write_string<G>(write: G) -> Result<String, SexpError>
+where
+    G: FnOnce(&mut StringSink) -> Result<(), SexpError>,
+{
+    let mut s = StringSink::new();
+    write(&mut s)?;
+    s.into_string()
+}
+
+struct Serializer<W, F = Adapter<CompactFormatter>> {
     writer: W,
     formatter: F,
+    options: Options,
+    buf: String,
+    // the nodes handed to the formatter, for a source map
+    recording: Option<Rc<RefCell<Recording>>>,
 }
 
 // dispatches only based on Formatter
 impl<W> Serializer<W>
 where
-    W: io::Write,
+    W: Sink,
 {
     fn new(writer: W) -> Self {
-        Serializer::with_formatter(writer, CompactFormatter)
+        Serializer::with_formatter(writer, Adapter::new(CompactFormatter))
     }
 }
 
-impl<W> Serializer<W, RulesFormatter>
+impl<W> Serializer<W, Adapter<RulesFormatter>>
 where
-    W: io::Write,
+    W: Sink,
+{
+    fn new_rules<R: Into<RuleSet>>(writer: W, rules: R) -> Self {
+        Serializer::with_formatter(writer, Adapter::new(RulesFormatter::new(rules)))
+    }
+}
+
+impl<W> Serializer<W, Adapter<KicadFormatter>>
+where
+    W: Sink,
 {
-    fn new_rules(writer: W, rules: Rules) -> Self {
-        Serializer::with_formatter(writer, RulesFormatter::new(rules))
+    fn new_kicad(writer: W, version: KicadVersion) -> Self {
+        let formatter = Adapter::new(KicadFormatter::new(version));
+        let mut ser = Serializer::with_formatter(writer, formatter);
+        let quoting = match version {
+            KicadVersion::Legacy => QuotingPolicy::KicadLegacy,
+            KicadVersion::V6 => QuotingPolicy::KicadV6,
+            KicadVersion::Modern => QuotingPolicy::Kicad,
+        };
+        // KiCad ends files with a newline
+        ser.options = Options::new()
+            .quoting(quoting)
+            .floats(FloatFormat::Kicad)
+            .final_newline(true);
+        ser
     }
 }
 
 impl<W, F> Serializer<W, F>
 where
-    W: io::Write,
-    F: Formatter,
+    W: Sink,
+    F: ContextFormatter,
 {
     fn with_formatter(writer: W, formatter: F) -> Self {
         Serializer {
-            writer: writer,
-            formatter: formatter,
+            writer,
+            formatter,
+            options: Options::new(),
+            buf: String::new(),
+            recording: None,
+        }
+    }
+
+    fn with_options(writer: W, formatter: F, options: &Options) -> Self {
+        let mut ser = Serializer::with_formatter(writer, formatter);
+        ser.options = options.clone();
+        ser
+    }
+
+    fn record<G: FnOnce(&mut Recording)>(&self, g: G) {
+        if let Some(ref recording) = self.recording {
+            g(&mut recording.borrow_mut())
         }
     }
 
-    fn serialize_str(&mut self, value: &str) -> Result<(), SexpError> {
-        write!(&mut self.writer, "{}", encode_string(value)).map_err(From::from)
+    fn serialize_str(&mut self, value: &str, context: &Context) -> Result<(), SexpError> {
+        let text = self.options.encode_string(context, value, &mut self.buf);
+        if let Some(ref recording) = self.recording {
+            recording.borrow_mut().atom(text.len());
+        }
+        self.formatter.atom(&mut self.writer, context, value, text)
     }
 
     fn serialize(&mut self, value: &Sexp) -> Result<(), SexpError> {
+        if let Sexp::Empty = *value {
+            return Ok(());
+        }
+        self.options.set_whitespace(&mut self.formatter);
+        self.serialize_in(value, &mut vec![], 0, 1)?;
+        self.options.write_final_newline(&mut self.writer)
+    }
+
+    // `heads` are the names of the lists containing `value`,
+    // which is at position `index` in the innermost list of length `len`
+    fn serialize_in<'a>(
+        &mut self,
+        value: &'a Sexp,
+        heads: &mut Vec<&'a str>,
+        index: usize,
+        len: usize,
+    ) -> Result<(), SexpError> {
         match *value {
-            Sexp::String(ref s) => self.serialize_str(s),
+            Sexp::String(ref s) => self.serialize_str(s, &Context::new(heads, index, len)),
+            Sexp::Bytes(ref b) => {
+                // the advanced form never needs quotes
+                let text = b.to_string();
+                let context = Context::new(heads, index, len);
+                self.record(|r| r.atom(text.len()));
+                self.formatter.atom(&mut self.writer, &context, &text, &text)
+            }
             Sexp::List(ref list) => {
-                let mut first = true;
-                if list.is_empty() {
-                    self.formatter.open(&mut self.writer, None)?;
-                } else {
-                    for v in list {
-                        if first {
-                            self.formatter.open(&mut self.writer, Some(v))?;
-                            self.serialize(v)?;
-                            first = false;
-                        } else {
-                            self.formatter.element(&mut self.writer, v)?;
-                            self.serialize(v)?;
-                        }
+                self.record(|r| r.open());
+                self.formatter
+                    .open(&mut self.writer, &Context::new(heads, index, len), list)?;
+                heads.push(match list.first() {
+                    Some(Sexp::String(s)) => s,
+                    _ => "",
+                });
+                for (i, v) in list.iter().enumerate() {
+                    if i > 0 {
+                        let context = Context::new(heads, i, list.len());
+                        self.formatter.element(&mut self.writer, &context, v)?;
                     }
+                    self.record(|r| r.enter(i));
+                    self.serialize_in(v, heads, i, list.len())?;
+                    self.record(|r| r.leave());
                 }
-                self.formatter.close(&mut self.writer)
+                heads.pop();
+                self.record(|r| r.close());
+                self.formatter
+                    .close(&mut self.writer, &Context::new(heads, index, len), list)
             }
             Sexp::Empty => Ok(()),
         }
@@ -75,12 +411,14 @@ where
 }
 
 /// serialize a symbolic-expression to a Writer
+///
+/// Strings are written straight from the tree without copying, but the
+/// formatters do many small writes, so wrap a `File` or socket in a `BufWriter`.
 pub fn to_writer<W>(writer: &mut W, value: &Sexp) -> Result<(), SexpError>
 where
     W: io::Write,
 {
-    let mut ser = Serializer::new(writer);
-    ser.serialize(value)
+    Serializer::new(IoSink(writer)).serialize(value)
 }
 
 /// serialize a symbolic-expression to a Writer using a Formatter
@@ -93,17 +431,100 @@ where
     W: io::Write,
     F: Formatter,
 {
-    let mut ser = Serializer::with_formatter(writer, formatter);
-    ser.serialize(value)
+    Serializer::with_formatter(IoSink(writer), Adapter::new(formatter)).serialize(value)
+}
+
+/// serialize a symbolic-expression to a Writer using a ContextFormatter
+pub fn to_writer_with_context_formatter<W, F>(
+    writer: &mut W,
+    formatter: F,
+    value: &Sexp,
+) -> Result<(), SexpError>
+where
+    W: io::Write,
+    F: ContextFormatter,
+{
+    Serializer::with_formatter(IoSink(writer), formatter).serialize(value)
+}
+
+/// serialize a symbolic-expression to a Writer using a ContextFormatter and Options
+pub fn to_writer_with_options<W, F>(
+    writer: &mut W,
+    formatter: F,
+    options: &Options,
+    value: &Sexp,
+) -> Result<(), SexpError>
+where
+    W: io::Write,
+    F: ContextFormatter,
+{
+    Serializer::with_options(IoSink(writer), formatter, options).serialize(value)
 }
 
 /// serialize a symbolic-expression to a Writer using a Rules Formatter
-pub fn to_writer_with_rules<W>(writer: &mut W, rules: Rules, value: &Sexp) -> Result<(), SexpError>
+pub fn to_writer_with_rules<W, R>(writer: &mut W, rules: R, value: &Sexp) -> Result<(), SexpError>
+where
+    W: io::Write,
+    R: Into<RuleSet>,
+{
+    Serializer::new_rules(IoSink(writer), rules).serialize(value)
+}
+
+/// serialize a symbolic-expression to a Writer the way KiCad writes it,
+/// using a `KicadFormatter` and KiCad's quoting of strings and numbers
+pub fn to_writer_kicad<W>(
+    writer: &mut W,
+    version: KicadVersion,
+    value: &Sexp,
+) -> Result<(), SexpError>
+where
+    W: io::Write,
+{
+    Serializer::new_kicad(IoSink(writer), version).serialize(value)
+}
+
+/// serialize a symbolic-expression to a Writer using a Style
+pub fn to_writer_with_style<W>(writer: &mut W, style: &Style, value: &Sexp) -> Result<(), SexpError>
 where
     W: io::Write,
 {
-    let mut ser = Serializer::new_rules(writer, rules);
-    ser.serialize(value)
+    let formatter = Adapter::new(style.formatter());
+    to_writer_with_options(writer, formatter, &style.options(), value)
+}
+
+/// serialize a symbolic-expression to a fmt::Write, like a String
+pub fn to_fmt_writer<W>(writer: &mut W, value: &Sexp) -> Result<(), SexpError>
+where
+    W: fmt::Write,
+{
+    Serializer::new(FmtSink(writer)).serialize(value)
+}
+
+/// serialize a symbolic-expression to a fmt::Write using a Formatter
+pub fn to_fmt_writer_with_formatter<W, F>(
+    writer: &mut W,
+    formatter: F,
+    value: &Sexp,
+) -> Result<(), SexpError>
+where
+    W: fmt::Write,
+    F: Formatter,
+{
+    Serializer::with_formatter(FmtSink(writer), Adapter::new(formatter)).serialize(value)
+}
+
+/// serialize a symbolic-expression to a fmt::Write using a ContextFormatter and Options
+pub fn to_fmt_writer_with_options<W, F>(
+    writer: &mut W,
+    formatter: F,
+    options: &Options,
+    value: &Sexp,
+) -> Result<(), SexpError>
+where
+    W: fmt::Write,
+    F: ContextFormatter,
+{
+    Serializer::with_options(FmtSink(writer), formatter, options).serialize(value)
 }
 
 /// serialize a symbolic-expression to a Vec<u8>
@@ -113,8 +534,8 @@ pub fn to_vec(value: &Sexp) -> Result<Vec<u8>, SexpError> {
     Ok(writer)
 }
 
-/// serialize a symbolic-expression to a Vec<u8> using Rules
-pub fn to_vec_with_rules(value: &Sexp, rules: Rules) -> Result<Vec<u8>, SexpError> {
+/// serialize a symbolic-expression to a Vec<u8> using Rules or a RuleSet
+pub fn to_vec_with_rules<R: Into<RuleSet>>(value: &Sexp, rules: R) -> Result<Vec<u8>, SexpError> {
     let mut writer = Vec::with_capacity(128);
     to_writer_with_rules(&mut writer, rules, value)?;
     Ok(writer)
@@ -132,16 +553,23 @@ where
 
 /// serialize a symbolic-expression to a String
 pub fn to_string(value: &Sexp) -> Result<String, SexpError> {
-    let vec = to_vec(value)?;
-    let string = String::from_utf8(vec)?;
-    Ok(string)
+    write_string(|s| Serializer::new(s).serialize(value))
+}
+
+/// serialize a symbolic-expression to a String using Rules or a RuleSet
+pub fn to_string_with_rules<R: Into<RuleSet>>(value: &Sexp, rules: R) -> Result<String, SexpError> {
+    write_string(|s| Serializer::new_rules(s, rules).serialize(value))
+}
+
+/// serialize a symbolic-expression to a String the way KiCad writes it
+pub fn to_string_kicad(value: &Sexp, version: KicadVersion) -> Result<String, SexpError> {
+    write_string(|s| Serializer::new_kicad(s, version).serialize(value))
 }
 
-/// serialize a symbolic-expression to a String using Rules
-pub fn to_string_with_rules(value: &Sexp, rules: Rules) -> Result<String, SexpError> {
-    let vec = to_vec_with_rules(value, rules)?;
-    let string = String::from_utf8(vec)?;
-    Ok(string)
+/// serialize a symbolic-expression to a String using a Style
+pub fn to_string_with_style(value: &Sexp, style: &Style) -> Result<String, SexpError> {
+    let formatter = Adapter::new(style.formatter());
+    write_string(|s| Serializer::with_options(s, formatter, &style.options()).serialize(value))
 }
 
 /// serialize a symbolic-expression to a String using a Formatter
@@ -149,7 +577,487 @@ pub fn to_string_with_formatter<F>(value: &Sexp, formatter: F) -> Result<String,
 where
     F: Formatter,
 {
-    let vec = to_vec_with_formatter(value, formatter)?;
-    let string = String::from_utf8(vec)?;
-    Ok(string)
+    write_string(|s| Serializer::with_formatter(s, Adapter::new(formatter)).serialize(value))
+}
+
+/// serialize a symbolic-expression to a String using a ContextFormatter
+pub fn to_string_with_context_formatter<F>(value: &Sexp, formatter: F) -> Result<String, SexpError>
+where
+    F: ContextFormatter,
+{
+    write_string(|s| Serializer::with_formatter(s, formatter).serialize(value))
+}
+
+/// serialize a symbolic-expression to a String using a ContextFormatter and Options
+pub fn to_string_with_options<F>(
+    value: &Sexp,
+    formatter: F,
+    options: &Options,
+) -> Result<String, SexpError>
+where
+    F: ContextFormatter,
+{
+    write_string(|s| Serializer::with_options(s, formatter, options).serialize(value))
+}
+
+/// serialize a symbolic-expression to a Writer using a ContextFormatter and Options,
+/// and return where every node ended up in the output
+///
+/// The nodes are found in the output as it passes to `writer`, so besides
+/// the parens and strings the formatter may only write whitespace and
+/// ANSI escape sequences; anything else is an error.
+pub fn to_writer_with_source_map<W, F>(
+    writer: &mut W,
+    formatter: F,
+    options: &Options,
+    value: &Sexp,
+) -> Result<SourceMap, SexpError>
+where
+    W: io::Write,
+    F: ContextFormatter,
+{
+    serialize_with_source_map(IoSink(writer), formatter, options, value)
+}
+
+/// serialize a symbolic-expression to a String using a ContextFormatter and Options,
+/// together with where every node ended up in it
+pub fn to_string_with_source_map<F>(
+    value: &Sexp,
+    formatter: F,
+    options: &Options,
+) -> Result<(String, SourceMap), SexpError>
+where
+    F: ContextFormatter,
+{
+    let mut s = String::with_capacity(128);
+    let map = serialize_with_source_map(&mut s, formatter, options, value)?;
+    Ok((s, map))
+}
+
+fn serialize_with_source_map<W, F>(
+    writer: W,
+    formatter: F,
+    options: &Options,
+    value: &Sexp,
+) -> Result<SourceMap, SexpError>
+where
+    W: Sink,
+    F: ContextFormatter,
+{
+    let recording = Rc::new(RefCell::new(Recording::default()));
+    let writer = Recorder::new(writer, recording.clone());
+    let mut ser = Serializer::with_options(writer, formatter, options);
+    ser.recording = Some(recording.clone());
+    ser.serialize(value)?;
+    let map = recording.borrow_mut().finish();
+    map
+}
+
+/// serialize a symbolic-expression to a Writer as a canonical
+/// symbolic-expression, where every string is prefixed with its length
+///
+/// shape: (3:abc[10:text/plain]5:hello)
+///
+/// Lists nested deeper than `parser::MAX_CANONICAL_DEPTH` are an error.
+pub fn to_writer_canonical<W>(writer: &mut W, value: &Sexp) -> Result<(), SexpError>
+where
+    W: io::Write,
+{
+    write_canonical(writer, value, 0)
+}
+
+// `depth` is the number of lists around `value`
+fn write_canonical<W>(writer: &mut W, value: &Sexp, depth: usize) -> Result<(), SexpError>
+where
+    W: io::Write,
+{
+    match *value {
+        Sexp::String(ref s) => write_canonical_string(writer, s.as_bytes()),
+        Sexp::Bytes(ref b) => {
+            if let Some(ref hint) = b.hint {
+                writer.write_all(b"[")?;
+                write_canonical_string(writer, hint)?;
+                writer.write_all(b"]")?;
+            }
+            write_canonical_string(writer, &b.data)
+        }
+        Sexp::List(ref list) => {
+            if depth == parser::MAX_CANONICAL_DEPTH {
+                return Err("lists nested too deep for a canonical symbolic-expression".into());
+            }
+            writer.write_all(b"(")?;
+            for v in list {
+                write_canonical(writer, v, depth + 1)?;
+            }
+            writer.write_all(b")")?;
+            Ok(())
+        }
+        Sexp::Empty => Ok(()),
+    }
+}
+
+/// serialize a symbolic-expression to bytes as a canonical symbolic-expression
+pub fn to_canonical(value: &Sexp) -> Result<Vec<u8>, SexpError> {
+    let mut writer = vec![];
+    to_writer_canonical(&mut writer, value)?;
+    Ok(writer)
+}
+
+// the length of `data`, ':' and `data`
+fn write_canonical_string<W: io::Write>(writer: &mut W, data: &[u8]) -> Result<(), SexpError> {
+    write!(writer, "{}:", data.len())?;
+    writer.write_all(data)?;
+    Ok(())
+}
+
+/// serialize a symbolic-expression to a file
+///
+/// The file is written next to `name` under a temporary name, synced to disk
+/// and renamed to `name`, so `name` is never left half written. An existing
+/// file keeps its permissions. Files ending in `.gz` or `.zst` are compressed
+/// when the `gzip` or `zstd` feature is enabled.
+pub fn to_file(name: &str, value: &Sexp) -> Result<(), SexpError> {
+    write_file(name, false, |writer| to_writer(writer, value))
+}
+
+/// serialize a symbolic-expression to a file using Rules or a RuleSet,
+/// like `to_file`
+pub fn to_file_with_rules<R>(name: &str, rules: R, value: &Sexp) -> Result<(), SexpError>
+where
+    R: Into<RuleSet>,
+{
+    write_file(name, false, |writer| to_writer_with_rules(writer, rules, value))
+}
+
+/// serialize a symbolic-expression to a file using a Formatter, like `to_file`
+pub fn to_file_with_formatter<F>(name: &str, formatter: F, value: &Sexp) -> Result<(), SexpError>
+where
+    F: Formatter,
+{
+    write_file(name, false, |writer| to_writer_with_formatter(writer, formatter, value))
+}
+
+/// serialize a symbolic-expression to a file using a ContextFormatter and
+/// Options, like `to_file`
+pub fn to_file_with_options<F>(
+    name: &str,
+    formatter: F,
+    options: &Options,
+    value: &Sexp,
+) -> Result<(), SexpError>
+where
+    F: ContextFormatter,
+{
+    write_file(name, options.backup, |writer| {
+        to_writer_with_options(writer, formatter, options, value)
+    })
+}
+
+// write a file through a temporary file in the same directory
+fn write_file<G>(name: &str, backup: bool, write: G) -> Result<(), SexpError>
+where
+    G: FnOnce(&mut Writer<BufWriter<File>>) -> Result<(), SexpError>,
+{
+    let compression = Compression::from_name(name);
+    // fail on unsupported compression before touching the file
+    Writer::new(io::sink(), compression)?;
+    let path = Path::new(name);
+    let file_name = match path.file_name() {
+        Some(file_name) => file_name.to_string_lossy(),
+        None => return Err(format!("{} is not a file name", name).into()),
+    };
+    let (tmp, f) = create_temporary(path, &file_name)?;
+    let permissions = fs::metadata(path).ok().map(|m| m.permissions());
+    let written = Writer::new(BufWriter::new(f), compression).and_then(|mut writer| {
+        write(&mut writer)?;
+        let f = writer.finish()?.into_inner().map_err(|e| e.into_error())?;
+        if let Some(permissions) = permissions {
+            f.set_permissions(permissions)?;
+        }
+        f.sync_all()?;
+        Ok(())
+    });
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    if backup && path.exists() {
+        fs::copy(path, format!("{}.bak", name))?;
+    }
+    fs::rename(&tmp, path)?;
+    // make the rename itself durable where directories can be synced
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+    Ok(())
+}
+
+// create a new temporary file next to `path`; the counter makes the name
+// unique between threads, and a name left by another process is skipped
+fn create_temporary(path: &Path, file_name: &str) -> Result<(PathBuf, File), SexpError> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    loop {
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let tmp = path.with_file_name(format!(".{}.{}.{}.tmp", file_name, process::id(), n));
+        match OpenOptions::new().write(true).create_new(true).open(&tmp) {
+            Ok(f) => return Ok((tmp, f)),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+// a list that is being written by a `SexpWriter`
+struct Level {
+    head: String,
+    // position in the containing list
+    index: usize,
+    // number of elements written so far
+    count: usize,
+    // has the formatter seen the '(' yet
+    opened: bool,
+}
+
+/// push-style writer that serializes a symbolic-expression
+/// without building it in memory first
+///
+/// The output is the same as serializing the equivalent `Sexp` with the
+/// same `Formatter`, as long as the formatter only looks at the name of
+/// the lists it is given. A list is only passed to the formatter once its
+/// first element is known.
+///
+/// The quoting callback of the `Options` can't know the number of values
+/// that follow, `Context::siblings` counts the values written so far.
+pub struct SexpWriter<W, F = CompactFormatter> {
+    writer: W,
+    formatter: F,
+    options: Options,
+    stack: Vec<Level>,
+    done: bool,
+    buf: String,
+    // memory reused for every string and list that is written
+    value: Sexp,
+    list: Vec<Sexp>,
+    heads: Vec<&'static str>,
+    // names of closed lists
+    spare: Vec<String>,
+}
+
+impl<W> SexpWriter<W>
+where
+    W: io::Write,
+{
+    /// create a `SexpWriter` that writes everything on one line
+    pub fn new(writer: W) -> Self {
+        SexpWriter::with_formatter(writer, CompactFormatter)
+    }
+}
+
+impl<W, F> SexpWriter<W, F>
+where
+    W: io::Write,
+    F: Formatter,
+{
+    /// create a `SexpWriter` using a Formatter
+    pub fn with_formatter(writer: W, formatter: F) -> Self {
+        SexpWriter {
+            writer,
+            formatter,
+            options: Options::new(),
+            stack: vec![],
+            done: false,
+            buf: String::new(),
+            value: Sexp::String(String::new()),
+            list: vec![],
+            heads: vec![],
+            spare: vec![],
+        }
+    }
+
+    /// use `options` for quoting, number formatting and whitespace
+    pub fn with_options(mut self, options: Options) -> Self {
+        if options.indent.is_some() || options.line_ending.is_some() {
+            let indent = options.indent.as_ref().map(|s| &s[..]);
+            self.formatter.set_whitespace(indent, options.line_ending);
+        }
+        self.options = options;
+        self
+    }
+
+    /// start a list with a name
+    ///
+    /// shape: (name
+    pub fn begin_list(&mut self, name: &str) -> Result<(), SexpError> {
+        self.begin_unnamed_list()?;
+        self.atom(name)
+    }
+
+    /// start a list of which the first element isn't a string
+    ///
+    /// shape: (
+    pub fn begin_unnamed_list(&mut self) -> Result<(), SexpError> {
+        let index = self.next_element(&Sexp::List(vec![]))?;
+        let mut head = self.spare.pop().unwrap_or_default();
+        head.clear();
+        self.stack.push(Level {
+            head,
+            index,
+            count: 0,
+            opened: false,
+        });
+        Ok(())
+    }
+
+    /// write a string, quoted when needed
+    pub fn atom(&mut self, s: &str) -> Result<(), SexpError> {
+        let mut value = mem::take(&mut self.value);
+        match value {
+            Sexp::String(ref mut v) => {
+                v.clear();
+                v.push_str(s);
+            }
+            _ => value = Sexp::String(s.into()),
+        }
+        let index = self.next_element(&value);
+        self.value = value;
+        let index = index?;
+        if index == 0 {
+            if let Some(level) = self.stack.last_mut() {
+                level.head.push_str(s);
+            }
+        }
+        let mut heads: Vec<&str> = mem::take(&mut self.heads);
+        heads.extend(self.stack.iter().map(|l| &l.head[..]));
+        let context = Context::new(&heads, index, index + 1);
+        let text = self.options.encode_string(&context, s, &mut self.buf);
+        let res = self.formatter.atom(&mut self.writer, text);
+        self.heads = recycle(heads);
+        res?;
+        if self.stack.is_empty() {
+            self.root_done()?;
+        }
+        Ok(())
+    }
+
+    /// write a number
+    pub fn number<N>(&mut self, n: N) -> Result<(), SexpError>
+    where
+        N: fmt::Display,
+    {
+        self.atom(&n.to_string())
+    }
+
+    /// write a complete symbolic-expression
+    pub fn value(&mut self, value: &Sexp) -> Result<(), SexpError> {
+        match *value {
+            Sexp::String(ref s) => self.atom(s),
+            Sexp::Bytes(ref b) => {
+                self.next_element(value)?;
+                self.formatter.atom(&mut self.writer, &b.to_string())?;
+                if self.stack.is_empty() {
+                    self.root_done()?;
+                }
+                Ok(())
+            }
+            Sexp::List(ref list) => {
+                self.begin_unnamed_list()?;
+                for v in list {
+                    self.value(v)?;
+                }
+                self.end_list()
+            }
+            Sexp::Empty => Ok(()),
+        }
+    }
+
+    /// end the innermost list
+    ///
+    /// shape: )
+    pub fn end_list(&mut self) -> Result<(), SexpError> {
+        if self.stack.is_empty() {
+            return Err("end_list without begin_list".into());
+        }
+        if !self.stack[self.stack.len() - 1].opened {
+            self.open_level(None)?;
+        }
+        if let Some(level) = self.stack.pop() {
+            self.spare.push(level.head);
+        }
+        self.formatter.close(&mut self.writer)?;
+        if self.stack.is_empty() {
+            self.root_done()?;
+        }
+        Ok(())
+    }
+
+    /// check that all lists are closed and return the writer
+    pub fn finish(self) -> Result<W, SexpError> {
+        if !self.stack.is_empty() {
+            return Err(format!("{} lists are not closed", self.stack.len()).into());
+        }
+        Ok(self.writer)
+    }
+
+    fn root_done(&mut self) -> Result<(), SexpError> {
+        self.done = true;
+        self.options.write_final_newline(&mut IoSink(&mut self.writer))
+    }
+
+    // place a new element in the innermost list and return its position
+    fn next_element(&mut self, value: &Sexp) -> Result<usize, SexpError> {
+        let l = self.stack.len();
+        if l == 0 {
+            if self.done {
+                return Err("only one root element can be written".into());
+            }
+            return Ok(0);
+        }
+        if !self.stack[l - 1].opened {
+            self.open_level(Some(value))?;
+        } else if !value.is_list() {
+            // lists are only passed to the formatter once their name is known
+            self.formatter.element(&mut self.writer, value)?;
+        }
+        let level = &mut self.stack[l - 1];
+        level.count += 1;
+        Ok(level.count - 1)
+    }
+
+    // pass the innermost list to the formatter now that its first element is known
+    fn open_level(&mut self, first: Option<&Sexp>) -> Result<(), SexpError> {
+        let index = self.stack[self.stack.len() - 1].index;
+        if index > 0 {
+            // the list as far as it is known
+            let mut items = mem::take(&mut self.list);
+            match (items.first_mut(), first) {
+                (Some(&mut Sexp::String(ref mut a)), Some(Sexp::String(b))) => {
+                    a.clear();
+                    a.push_str(b);
+                }
+                (_, first) => {
+                    items.clear();
+                    items.extend(first.cloned());
+                }
+            }
+            let list = Sexp::List(items);
+            let res = self.formatter.element(&mut self.writer, &list);
+            if let Sexp::List(items) = list {
+                self.list = items;
+            }
+            res?;
+        }
+        self.formatter.open(&mut self.writer, first)?;
+        let l = self.stack.len();
+        self.stack[l - 1].opened = true;
+        Ok(())
+    }
+}
+
+// an empty Vec with the memory of `v`, which can hold strings of any lifetime
+fn recycle(mut v: Vec<&str>) -> Vec<&'static str> {
+    v.clear();
+    v.into_iter().map(|_| "").collect()
 }
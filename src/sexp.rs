@@ -1,68 +1,72 @@
 // (c) 2016-2017 Productize SPRL <joost@productize.be>
 
-use std::fmt;
+use std::fmt::{self, Write};
 use std::mem;
 
 use error::SexpError;
+use float::FloatFormat;
+use formatter::PrettyFormatter;
+use ser;
 /// like Into trait but works from a ref avoiding consumption or expensive clone
 pub trait IntoSexp {
     /// convert self into a Sexp
+    #[allow(clippy::wrong_self_convention)]
     fn into_sexp(&self) -> Sexp;
 }
 
-impl Into<Sexp> for Vec<Sexp> {
-    fn into(self: Vec<Sexp>) -> Sexp {
-        Sexp::List(self)
+impl From<Vec<Sexp>> for Sexp {
+    fn from(v: Vec<Sexp>) -> Sexp {
+        Sexp::List(v)
     }
 }
 
-impl Into<Sexp> for String {
-    fn into(self: String) -> Sexp {
-        Sexp::String(self)
+impl From<String> for Sexp {
+    fn from(s: String) -> Sexp {
+        Sexp::String(s)
     }
 }
 
-impl<'a> From<&'a str> for Sexp {
+impl From<&str> for Sexp {
     fn from(t: &str) -> Sexp {
         Sexp::String(t.into())
     }
 }
 
-impl<'a> From<&'a String> for Sexp {
+impl From<&String> for Sexp {
     fn from(t: &String) -> Sexp {
         Sexp::String(t.clone())
     }
 }
 
-impl Into<Sexp> for i64 {
-    fn into(self: i64) -> Sexp {
-        Sexp::String(format!("{}", self))
+impl From<Bytes> for Sexp {
+    fn from(b: Bytes) -> Sexp {
+        Sexp::Bytes(Box::new(b))
+    }
+}
+
+impl From<i64> for Sexp {
+    fn from(i: i64) -> Sexp {
+        Sexp::String(format!("{}", i))
     }
 }
 
-impl Into<Sexp> for f64 {
-    fn into(self: f64) -> Sexp {
-        Sexp::String(format!("{}", self))
+impl From<f64> for Sexp {
+    fn from(f: f64) -> Sexp {
+        FloatFormat::Shortest.to_sexp(f)
     }
 }
 
-impl<'a> From<(&'a str, Sexp)> for Sexp {
+impl From<(&str, Sexp)> for Sexp {
     fn from(kv: (&str, Sexp)) -> Sexp {
         let (name, value) = kv;
-        let mut v = vec![];
-        v.push(name.into());
-        v.push(value);
-        v.into()
+        vec![name.into(), value].into()
     }
 }
 
 impl<'a, T: fmt::Display> From<(&'a str, &'a T)> for Sexp {
     fn from(kv: (&str, &T)) -> Sexp {
         let (name, value) = kv;
-        let mut v = vec![];
-        v.push(name.into());
-        v.push(format!("{}", value).into());
-        v.into()
+        vec![name.into(), format!("{}", value).into()].into()
     }
 }
 
@@ -71,14 +75,23 @@ impl<'a, T: fmt::Display> From<(&'a str, &'a T)> for Sexp {
 ///
 /// `String` shape: hello
 /// `List` shape: (...)
+/// `Bytes` shape: [image/png]#89504e47#
 /// `Empty shape:
-#[derive(Debug, Clone, PartialEq)]
+///
+/// New kinds of symbolic-expressions may be added, so a `match` needs a `_` arm.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[non_exhaustive]
 pub enum Sexp {
     /// plain String symbolic-expression
     String(String),
     /// list symbolic-expression
     List(Vec<Sexp>),
+    /// string of bytes that isn't UTF-8 or has a display hint,
+    /// as found in canonical symbolic-expressions; boxed, as it's rare
+    /// and would otherwise double the size of every `Sexp`
+    Bytes(Box<Bytes>),
     /// empty, trivial symbolic-expression
+    #[default]
     Empty,
 }
 
@@ -100,21 +113,81 @@ pub enum Sexp {
 // Joost remark: kicad no longer seems to follow Rule 4.
 // so we don't either
 
-/// encode a string according to the guidelines given by Kicad
-pub fn encode_string(s: &str) -> String {
+pub(crate) fn needs_quotes(s: &str) -> bool {
     // fn rule_4(s:&str) -> bool {
     // s.contains('-') && s.len() > 1 && s.as_bytes()[0] != 45
     // }
-    if s.contains('(') || s.contains(' ') || s.contains(')') || s.contains('\t') || s.contains('{')
-        || s.contains('}') || s.contains('}') || s.contains('%') || s.is_empty()
+    s.is_empty() || s.starts_with('"')
+        || s.bytes()
+            .any(|c| matches!(c, b'(' | b' ' | b')' | b'\t' | b'{' | b'}' | b'%'))
     // || rule_4(s)
-    {
-        format!("\"{}\"", s)
+}
+
+// push `s` in quotes to `buf`; parsed strings keep their escape sequences,
+// so only a '"' that isn't escaped yet gets a '\\' like the scanner expects
+pub(crate) fn push_quoted(buf: &mut String, s: &str) {
+    buf.push('"');
+    let mut escaped = false;
+    for c in s.chars() {
+        if c == '"' && !escaped {
+            buf.push('\\');
+        }
+        escaped = c == '\\';
+        buf.push(c);
+    }
+    buf.push('"');
+}
+
+/// encode a string according to the guidelines given by Kicad
+pub fn encode_string(s: &str) -> String {
+    if needs_quotes(s) {
+        let mut buf = String::with_capacity(s.len() + 2);
+        push_quoted(&mut buf, s);
+        buf
     } else {
         String::from(s)
     }
 }
 
+/// a string of bytes with an optional display hint, like a MIME type,
+/// as in Rivest's canonical symbolic-expressions
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Bytes {
+    /// the display hint
+    pub hint: Option<Vec<u8>>,
+    /// the bytes
+    pub data: Vec<u8>,
+}
+
+/// writes Rivest's advanced form: a token when the bytes are one,
+/// otherwise hexadecimal between '#'
+///
+/// shape: [text/plain]#68656c6c6f20776f726c64#
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        if let Some(ref hint) = self.hint {
+            f.write_char('[')?;
+            write_octets(f, hint)?;
+            f.write_char(']')?;
+        }
+        write_octets(f, &self.data)
+    }
+}
+
+// `data` as a token when it is one, otherwise in hexadecimal
+fn write_octets(f: &mut fmt::Formatter, data: &[u8]) -> Result<(), fmt::Error> {
+    let token = data.first().is_some_and(|c| !c.is_ascii_digit())
+        && data.iter().all(|&c| c.is_ascii_alphanumeric() || b"-./_:*+=".contains(&c));
+    if token {
+        return data.iter().try_for_each(|&c| f.write_char(c as char));
+    }
+    f.write_char('#')?;
+    for c in data {
+        write!(f, "{:02x}", c)?;
+    }
+    f.write_char('#')
+}
+
 impl Sexp {
     /// create an empty symbolic-expression
     #[deprecated(since = "4.0.0", note = "please use `Sexp::default()` instead")]
@@ -139,9 +212,7 @@ impl Sexp {
 
     /// create an empty list type symbolic-expression
     pub fn start(name: &str) -> Sexp {
-        let mut v = vec![];
-        v.push(Sexp::String(name.into()));
-        Sexp::List(v)
+        Sexp::List(vec![Sexp::String(name.into())])
     }
 
     /// push an element in a list
@@ -162,8 +233,7 @@ impl Sexp {
     where
         F: Fn(&mut Vec<Sexp>),
     {
-        let mut v = vec![];
-        v.push(name.into());
+        let mut v = vec![name.into()];
         fill(&mut v);
         Sexp::List(v)
     }
@@ -176,10 +246,7 @@ impl Sexp {
     where
         T: fmt::Display,
     {
-        let mut v = vec![];
-        v.push(name.into());
-        v.push(format!("{}", value).into());
-        v.into()
+        vec![name.into(), format!("{}", value).into()].into()
     }
 
     /// create a list type symbolic-expression where
@@ -191,10 +258,7 @@ impl Sexp {
     where
         T: IntoSexp,
     {
-        let mut v = vec![];
-        v.push(name.into());
-        v.push(value.into_sexp());
-        Sexp::List(v)
+        Sexp::List(vec![name.into(), value.into_sexp()])
     }
 
     /// if the expression is a list, extract the `Vec<Sexp>`
@@ -247,20 +311,23 @@ impl Sexp {
         }
     }
 
-    /// is this expression a string
-    pub fn is_string(&self) -> bool {
+    /// access a `String` or `Bytes` symbolic-expression as its bytes
+    pub fn bytes(&self) -> Result<&[u8], SexpError> {
         match *self {
-            Sexp::String(_) => true,
-            _ => false,
+            Sexp::String(ref s) => Ok(s.as_bytes()),
+            Sexp::Bytes(ref b) => Ok(&b.data),
+            _ => Err(format!("not a string: {}", self).into()),
         }
     }
 
+    /// is this expression a string
+    pub fn is_string(&self) -> bool {
+        matches!(*self, Sexp::String(_))
+    }
+
     /// is this expression a list
     pub fn is_list(&self) -> bool {
-        match *self {
-            Sexp::List(_) => true,
-            _ => false,
-        }
+        matches!(*self, Sexp::List(_))
     }
 
     /// access the symbolic-expression as if it is a String
@@ -369,29 +436,16 @@ impl Sexp {
     }
 }
 
+/// `{}` writes everything on one line, `{:#}` lays it out with a
+/// `PrettyFormatter` for the width, 80 when not given
 impl fmt::Display for Sexp {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        match *self {
-            Sexp::String(ref s) => write!(f, "{}", encode_string(s)),
-            Sexp::List(ref v) => {
-                write!(f, "(")?;
-                let l = v.len();
-                for (i, x) in v.iter().enumerate() {
-                    if i < l - 1 {
-                        write!(f, "{} ", x)?;
-                    } else {
-                        write!(f, "{}", x)?;
-                    }
-                }
-                write!(f, ")")
-            }
-            Sexp::Empty => Ok(()),
-        }
-    }
-}
-
-impl Default for Sexp {
-    fn default() -> Sexp {
-        Sexp::Empty
+        let res = if f.alternate() {
+            let pretty = PrettyFormatter::new(f.width().unwrap_or(80));
+            ser::to_fmt_writer_with_formatter(f, pretty, self)
+        } else {
+            ser::to_fmt_writer(f, self)
+        };
+        res.map_err(|_| fmt::Error)
     }
 }
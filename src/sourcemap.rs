@@ -0,0 +1,321 @@
+// (c) 2017 Productize SPRL <joost@productize.be>
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+
+use error::SexpError;
+use formatter::Sink;
+use scanner::{Event, Position, Scanner};
+
+/// location of a node of a symbolic-expression in a text
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    /// position of the node in each list containing it, outermost first;
+    /// empty for the root
+    pub path: Vec<usize>,
+    /// byte offset of the first character
+    pub start: usize,
+    /// byte offset just after the last character
+    pub end: usize,
+    /// line of the first character, starting from 0
+    pub line: usize,
+    /// column of the first character in characters, starting from 0
+    pub col: usize,
+    /// line just after the last character
+    pub end_line: usize,
+    /// column just after the last character
+    pub end_col: usize,
+}
+
+impl Span {
+    fn set_start(&mut self, p: Position) {
+        self.start = p.offset;
+        self.line = p.line;
+        self.col = p.col;
+    }
+
+    fn set_end(&mut self, p: Position) {
+        self.end = p.offset;
+        self.end_line = p.line;
+        self.end_col = p.col;
+    }
+
+    fn new(path: Vec<usize>, start: Position, end: Position) -> Span {
+        Span {
+            path,
+            start: start.offset,
+            end: end.offset,
+            line: start.line,
+            col: start.col,
+            end_line: end.line,
+            end_col: end.col,
+        }
+    }
+}
+
+/// where every list and string of a symbolic-expression is in a text,
+/// like the output of a serializer
+///
+/// The serializer records where it writes every node, so the paths are
+/// those of the serialized tree and a `Sexp::Empty` has no span. `build`
+/// maps a text as it is parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceMap {
+    spans: Vec<Span>,
+    paths: HashMap<Vec<usize>, usize>,
+}
+
+impl SourceMap {
+    /// map the symbolic-expression in a &str
+    pub fn build(text: &str) -> Result<SourceMap, SexpError> {
+        let mut spans = vec![];
+        let mut scanner = Scanner::new(text);
+        scanner.eat_space();
+        if !scanner.eof() {
+            // the open lists as their span and the number of elements seen
+            let mut stack: Vec<(usize, usize)> = vec![];
+            scanner.walk(&mut |event| match event {
+                Event::Open(p) => {
+                    let path = next_path(&spans, &mut stack);
+                    stack.push((spans.len(), 0));
+                    spans.push(Span::new(path, p, p));
+                }
+                Event::Atom(p, raw) => {
+                    let path = next_path(&spans, &mut stack);
+                    spans.push(Span::new(path, p, advance(p, raw)));
+                }
+                Event::Close(p) => {
+                    if let Some((index, _)) = stack.pop() {
+                        spans[index].end = p.offset;
+                        spans[index].end_line = p.line;
+                        spans[index].end_col = p.col;
+                    }
+                }
+            })?;
+        }
+        Ok(SourceMap::from_spans(spans))
+    }
+
+    fn from_spans(spans: Vec<Span>) -> SourceMap {
+        let paths = spans.iter().enumerate().map(|(i, s)| (s.path.clone(), i)).collect();
+        SourceMap { spans, paths }
+    }
+
+    /// all spans, in the order they start
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    /// the span of the node at `path`
+    pub fn get(&self, path: &[usize]) -> Option<&Span> {
+        self.paths.get(path).map(|&i| &self.spans[i])
+    }
+
+    /// the innermost node that contains the byte at `offset`
+    pub fn at(&self, offset: usize) -> Option<&Span> {
+        self.spans
+            .iter()
+            .take_while(|s| s.start <= offset)
+            .filter(|s| offset < s.end)
+            .last()
+    }
+}
+
+// the path of the next element of the innermost open list
+fn next_path(spans: &[Span], stack: &mut [(usize, usize)]) -> Vec<usize> {
+    match stack.last_mut() {
+        Some(&mut (parent, ref mut count)) => {
+            let mut path = spans[parent].path.clone();
+            path.push(*count);
+            *count += 1;
+            path
+        }
+        None => vec![],
+    }
+}
+
+// the position after `s` when it starts at `p`
+fn advance(mut p: Position, s: &str) -> Position {
+    for c in s.bytes() {
+        p.offset += 1;
+        if c & 0xC0 != 0x80 {
+            p.col += 1;
+        }
+        if c == b'\n' {
+            p.line += 1;
+            p.col = 0;
+        }
+    }
+    p
+}
+
+// a token the serializer handed to the formatter, which is found in the
+// output when the formatter writes it, possibly much later
+enum Expected {
+    Open(usize),
+    // the span and the length of the text
+    Atom(usize, usize),
+    Close(usize),
+}
+
+/// what the serializer writes, shared between the serializer, which
+/// records every node it hands to the formatter, and a `Recorder`, which
+/// finds those nodes in the text the formatter writes
+#[derive(Default)]
+pub(crate) struct Recording {
+    spans: Vec<Span>,
+    path: Vec<usize>,
+    // spans of the lists that are open
+    lists: Vec<usize>,
+    expected: VecDeque<Expected>,
+    position: Position,
+    // the span of the atom being written and how many bytes are left of it
+    atom: Option<(usize, usize)>,
+    // inside an ANSI escape sequence, like the ones of `ColorFormatter`
+    escape: bool,
+    // where the output stopped matching the tree
+    error: Option<Position>,
+}
+
+impl Recording {
+    fn start(&mut self) -> usize {
+        self.spans.push(Span::new(self.path.clone(), self.position, self.position));
+        self.spans.len() - 1
+    }
+
+    /// the next node is element `index` of the innermost list
+    pub(crate) fn enter(&mut self, index: usize) {
+        self.path.push(index);
+    }
+
+    /// done with the node entered last
+    pub(crate) fn leave(&mut self) {
+        self.path.pop();
+    }
+
+    /// a list is opened
+    pub(crate) fn open(&mut self) {
+        let i = self.start();
+        self.lists.push(i);
+        self.expected.push_back(Expected::Open(i));
+    }
+
+    /// a string is written as `len` bytes of text
+    pub(crate) fn atom(&mut self, len: usize) {
+        let i = self.start();
+        self.expected.push_back(Expected::Atom(i, len));
+    }
+
+    /// the innermost open list is closed
+    pub(crate) fn close(&mut self) {
+        if let Some(i) = self.lists.pop() {
+            self.expected.push_back(Expected::Close(i));
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) {
+        for &c in buf {
+            self.byte(c);
+        }
+    }
+
+    fn byte(&mut self, c: u8) {
+        let start = self.position;
+        self.position.offset += 1;
+        if c == b'\n' {
+            self.position.line += 1;
+            self.position.col = 0;
+        } else if c & 0xC0 != 0x80 {
+            self.position.col += 1;
+        }
+        if let Some((i, left)) = self.atom.take() {
+            if left > 1 {
+                self.atom = Some((i, left - 1));
+            } else {
+                self.end(i);
+            }
+            return;
+        }
+        if self.escape {
+            self.escape = c == b'[' || !(b'@'..=b'~').contains(&c);
+            return;
+        }
+        if c == 0x1b {
+            self.escape = true;
+            return;
+        }
+        if c.is_ascii_whitespace() {
+            return;
+        }
+        // an empty atom takes no bytes: it is where the next token starts
+        while let Some(&Expected::Atom(i, 0)) = self.expected.front() {
+            self.spans[i].set_start(start);
+            self.spans[i].set_end(start);
+            self.expected.pop_front();
+        }
+        match self.expected.pop_front() {
+            Some(Expected::Open(i)) if c == b'(' => self.spans[i].set_start(start),
+            Some(Expected::Close(i)) if c == b')' => self.end(i),
+            Some(Expected::Atom(i, len)) => {
+                self.spans[i].set_start(start);
+                if len > 1 {
+                    self.atom = Some((i, len - 1));
+                } else {
+                    self.end(i);
+                }
+            }
+            _ => {
+                if self.error.is_none() {
+                    self.error = Some(start);
+                }
+            }
+        }
+    }
+
+    fn end(&mut self, i: usize) {
+        let position = self.position;
+        self.spans[i].set_end(position);
+    }
+
+    /// the source map of the complete output
+    pub(crate) fn finish(&mut self) -> Result<SourceMap, SexpError> {
+        let position = self.position;
+        while let Some(&Expected::Atom(i, 0)) = self.expected.front() {
+            self.spans[i].set_start(position);
+            self.spans[i].set_end(position);
+            self.expected.pop_front();
+        }
+        let p = match self.error {
+            Some(p) => p,
+            None if !self.expected.is_empty() || self.atom.is_some() => position,
+            None => return Ok(SourceMap::from_spans(self.spans.split_off(0))),
+        };
+        Err(format!(
+            "the output of the formatter doesn't match the serialized value at line {}, col {}",
+            p.line + 1,
+            p.col + 1
+        ).into())
+    }
+}
+
+/// a writer that passes everything to `writer` and finds in it the
+/// nodes of a `Recording`
+pub(crate) struct Recorder<W> {
+    writer: W,
+    recording: Rc<RefCell<Recording>>,
+}
+
+impl<W: Sink> Recorder<W> {
+    pub(crate) fn new(writer: W, recording: Rc<RefCell<Recording>>) -> Recorder<W> {
+        Recorder { writer, recording }
+    }
+}
+
+impl<W: Sink> Sink for Recorder<W> {
+    fn write_str(&mut self, s: &str) -> Result<(), SexpError> {
+        self.writer.write_str(s)?;
+        self.recording.borrow_mut().write(s.as_bytes());
+        Ok(())
+    }
+}
@@ -0,0 +1,330 @@
+// (c) 2017 Productize SPRL <joost@productize.be>
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::prelude::*;
+
+use Sexp;
+use error::SexpError;
+use float::FloatFormat;
+use formatter::{Formatter, Indent, LineEnding, Rule, RuleSet, RulesFormatter};
+use iteratom::{FromSexp, IterAtom};
+use infer;
+use parser::{self, read_file};
+use ser::{self, Options, QuotingPolicy};
+
+const STYLE_VERSION: i64 = 1;
+
+/// a complete formatting style: layout rules, indentation, line width,
+/// quoting and number formatting
+///
+/// A style can be kept in a configuration file:
+///
+/// ```text
+/// (format_style (version 1)
+///   (indent (spaces 2))
+///   (width 100)
+///   (line_ending crlf)
+///   (final_newline)
+///   (quoting kicad)
+///   (floats (trimmed 6))
+///   (rule layer (newline) (indent 1))
+///   (rule (module pad) (newline) (absolute_indent 1) (close_on_own_line)))
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Style {
+    /// layout rules per name or path of names
+    pub rules: RuleSet,
+    /// indentation, two spaces when not set
+    pub indent: Option<String>,
+    /// maximum line width
+    pub width: Option<usize>,
+    /// how lines are ended
+    pub line_ending: LineEnding,
+    /// end the output with a line ending
+    pub final_newline: bool,
+    /// how strings are quoted
+    pub quoting: QuotingPolicy,
+    /// how numbers with a fraction are written
+    pub floats: Option<FloatFormat>,
+}
+
+impl Style {
+    /// parse a style from a &str
+    pub fn parse_str(s: &str) -> Result<Style, SexpError> {
+        Style::from_sexp(&parser::parse_str(s)?)
+    }
+
+    /// load a style from a configuration file
+    pub fn load(name: &str) -> Result<Style, SexpError> {
+        Style::from_sexp(&parser::parse_file(name)?)
+    }
+
+    /// derive the style of a text, so that a symbolic-expression parsed
+    /// from it can be written back in the same layout
+    pub fn infer(text: &str) -> Result<Style, SexpError> {
+        infer::infer(text)
+    }
+
+    /// derive the style of a file
+    pub fn infer_file(name: &str) -> Result<Style, SexpError> {
+        infer::infer(&read_file(name)?)
+    }
+
+    /// save the style to a configuration file
+    pub fn save(&self, name: &str) -> Result<(), SexpError> {
+        let mut rules = RuleSet::new();
+        rules.insert("rule", Rule {
+            newline_before: true,
+            ..Default::default()
+        });
+        let s = ser::to_string_with_rules(&self.to_sexp(), rules)?;
+        let mut f = File::create(name)?;
+        f.write_all(s.as_bytes())?;
+        Ok(())
+    }
+
+    /// the formatter for the layout of the style
+    pub fn formatter(&self) -> RulesFormatter {
+        let mut f = RulesFormatter::new(self.rules.clone());
+        let indent = self.indent.as_ref().map(|s| &s[..]);
+        f.set_whitespace(indent, Some(self.line_ending));
+        match self.width {
+            Some(width) => f.with_width(width),
+            None => f,
+        }
+    }
+
+    /// the serialization options of the style
+    pub fn options(&self) -> Options {
+        let options = Options::new()
+            .quoting(self.quoting)
+            .line_ending(self.line_ending)
+            .final_newline(self.final_newline);
+        match self.floats {
+            Some(floats) => options.floats(floats),
+            None => options,
+        }
+    }
+
+    /// convert the style to a symbolic-expression
+    pub fn to_sexp(&self) -> Sexp {
+        let mut v = vec!["format_style".into(), ("version", &STYLE_VERSION).into()];
+        if let Some(ref indent) = self.indent {
+            v.push(("indent", indent_to_sexp(indent)).into());
+        }
+        if let Some(width) = self.width {
+            v.push(("width", &width).into());
+        }
+        if self.line_ending == LineEnding::CrLf {
+            v.push(("line_ending", &"crlf").into());
+        }
+        if self.final_newline {
+            v.push(Sexp::List(vec!["final_newline".into()]));
+        }
+        v.push(("quoting", &quoting_name(self.quoting)).into());
+        if let Some(floats) = self.floats {
+            v.push(("floats", floats_to_sexp(floats)).into());
+        }
+        let mut rules: Vec<_> = self.rules.iter().collect();
+        rules.sort_by(|a, b| a.0.cmp(b.0));
+        for (path, rule) in rules {
+            v.push(rule_to_sexp(path, rule));
+        }
+        Sexp::List(v)
+    }
+}
+
+impl FromSexp for Style {
+    fn from_sexp(s: &Sexp) -> Result<Style, SexpError> {
+        let mut style = Style::default();
+        for e in IterAtom::new(s, "format_style")?.iter {
+            let name = e.list_name()?;
+            let args = &e.list()?[1..];
+            match &name[..] {
+                "version" => {
+                    let version = one(e, args)?.i()?;
+                    if version != STYLE_VERSION {
+                        return Err(format!("unsupported style version {}", version).into());
+                    }
+                }
+                "indent" => style.indent = Some(indent_from_sexp(one(e, args)?)?),
+                "width" => style.width = Some(size(e, one(e, args)?.i()?)?),
+                "line_ending" => {
+                    style.line_ending = match &one(e, args)?.string()?[..] {
+                        "lf" => LineEnding::Lf,
+                        "crlf" => LineEnding::CrLf,
+                        name => return Err(format!("unknown line ending {}", name).into()),
+                    }
+                }
+                "final_newline" => match args.len() {
+                    0 => style.final_newline = true,
+                    _ => return Err(format!("unexpected value in {}", e).into()),
+                },
+                "quoting" => style.quoting = quoting_from_name(one(e, args)?.string()?)?,
+                "floats" => style.floats = Some(floats_from_sexp(one(e, args)?)?),
+                "rule" => {
+                    let (path, rule) = rule_from_sexp(e, args)?;
+                    let path: Vec<&str> = path.iter().map(|s| &s[..]).collect();
+                    style.rules.insert_path(&path, rule);
+                }
+                _ => return Err(format!("unknown style setting {}", e).into()),
+            }
+        }
+        Ok(style)
+    }
+}
+
+fn one<'a>(e: &Sexp, args: &'a [Sexp]) -> Result<&'a Sexp, SexpError> {
+    match args.len() {
+        1 => Ok(&args[0]),
+        _ => Err(format!("expected one value in {}", e).into()),
+    }
+}
+
+// `n` of `e` as a size, which can't be negative
+fn size(e: &Sexp, n: i64) -> Result<usize, SexpError> {
+    usize::try_from(n).map_err(|_| format!("negative size in {}", e).into())
+}
+
+const QUOTING_NAMES: &[(QuotingPolicy, &str)] = &[
+    (QuotingPolicy::Default, "default"),
+    (QuotingPolicy::Minimal, "minimal"),
+    (QuotingPolicy::Always, "always"),
+    (QuotingPolicy::KicadLegacy, "kicad_legacy"),
+    (QuotingPolicy::KicadV6, "kicad_v6"),
+    (QuotingPolicy::Kicad, "kicad"),
+    (QuotingPolicy::Lisp, "lisp"),
+];
+
+fn quoting_name(policy: QuotingPolicy) -> &'static str {
+    QUOTING_NAMES.iter().find(|q| q.0 == policy).map(|q| q.1).unwrap_or("default")
+}
+
+fn quoting_from_name(name: &str) -> Result<QuotingPolicy, SexpError> {
+    match QUOTING_NAMES.iter().find(|q| q.1 == name) {
+        Some(q) => Ok(q.0),
+        None => Err(format!("unknown quoting {}", name).into()),
+    }
+}
+
+// shape: tab | (spaces 2) | string
+fn indent_from_sexp(s: &Sexp) -> Result<String, SexpError> {
+    match *s {
+        Sexp::String(ref indent) if indent == "tab" => Ok("\t".into()),
+        Sexp::String(ref indent) => Ok(indent.clone()),
+        _ => {
+            let spaces = IterAtom::new(s, "spaces")?.i("spaces")?;
+            Ok(" ".repeat(size(s, spaces)?))
+        }
+    }
+}
+
+fn indent_to_sexp(indent: &str) -> Sexp {
+    if indent == "\t" {
+        "tab".into()
+    } else if indent.bytes().all(|c| c == b' ') {
+        ("spaces", &indent.len()).into()
+    } else {
+        indent.into()
+    }
+}
+
+// shape: shortest | kicad | (fixed 3) | (trimmed 6)
+fn floats_from_sexp(s: &Sexp) -> Result<FloatFormat, SexpError> {
+    if let Sexp::String(ref name) = *s {
+        return match &name[..] {
+            "shortest" => Ok(FloatFormat::Shortest),
+            "kicad" => Ok(FloatFormat::Kicad),
+            _ => Err(format!("unknown float format {}", name).into()),
+        };
+    }
+    let name = s.list_name()?;
+    let decimals = size(s, one(s, &s.list()?[1..])?.i()?)?;
+    match &name[..] {
+        "fixed" => Ok(FloatFormat::Fixed(decimals)),
+        "trimmed" => Ok(FloatFormat::Trimmed(decimals)),
+        _ => Err(format!("unknown float format {}", s).into()),
+    }
+}
+
+fn floats_to_sexp(floats: FloatFormat) -> Sexp {
+    match floats {
+        FloatFormat::Shortest => "shortest".into(),
+        FloatFormat::Kicad => "kicad".into(),
+        FloatFormat::Fixed(d) => ("fixed", &d).into(),
+        FloatFormat::Trimmed(d) => ("trimmed", &d).into(),
+    }
+}
+
+// shape: (rule name|(path ...) (newline) (indent 1) ...)
+fn rule_from_sexp(e: &Sexp, args: &[Sexp]) -> Result<(Vec<String>, Rule), SexpError> {
+    let path = match args.first() {
+        Some(Sexp::String(name)) => vec![name.clone()],
+        Some(Sexp::List(names)) => {
+            let mut path = vec![];
+            for name in names {
+                path.push(name.s()?);
+            }
+            path
+        }
+        _ => return Err(format!("missing name in {}", e).into()),
+    };
+    if path.is_empty() {
+        return Err(format!("empty path in {}", e).into());
+    }
+    let mut rule = Rule::default();
+    for setting in &args[1..] {
+        let name = setting.list_name()?;
+        let values = &setting.list()?[1..];
+        let flag = || -> Result<bool, SexpError> {
+            match values.len() {
+                0 => Ok(true),
+                _ => Err(format!("unexpected value in {}", setting).into()),
+            }
+        };
+        let value = || size(setting, one(setting, values)?.i()?);
+        match &name[..] {
+            "newline" => rule.newline_before = flag()?,
+            "children_on_own_line" => rule.children_on_own_line = flag()?,
+            "close_on_own_line" => rule.close_on_own_line = flag()?,
+            "align" => rule.align = flag()?,
+            "indent" => rule.indent = Indent::Relative(value()?),
+            "absolute_indent" => rule.indent = Indent::Absolute(value()?),
+            "max_items" => rule.max_items_per_line = Some(value()?),
+            _ => return Err(format!("unknown rule setting {}", setting).into()),
+        }
+    }
+    Ok((path, rule))
+}
+
+fn rule_to_sexp(path: &[String], rule: &Rule) -> Sexp {
+    let mut r = Sexp::start("rule");
+    if path.len() == 1 {
+        r.push(&path[0]);
+    } else {
+        r.push(Sexp::List(path.iter().map(|s| s.into()).collect()));
+    }
+    let flag = |name: &str| Sexp::List(vec![name.into()]);
+    if rule.newline_before {
+        r.push(flag("newline"));
+    }
+    match rule.indent {
+        Indent::Relative(1) => (),
+        Indent::Relative(n) => r.push(("indent", &n)),
+        Indent::Absolute(n) => r.push(("absolute_indent", &n)),
+    }
+    if rule.children_on_own_line {
+        r.push(flag("children_on_own_line"));
+    }
+    if rule.close_on_own_line {
+        r.push(flag("close_on_own_line"));
+    }
+    if let Some(n) = rule.max_items_per_line {
+        r.push(("max_items", &n));
+    }
+    if rule.align {
+        r.push(flag("align"));
+    }
+    r
+}
@@ -2,7 +2,16 @@
 
 use ser;
 use formatter;
+use formatter::Sink;
 use parser;
+use kicad;
+use float;
+use {Bytes, Sexp};
+use error::SexpError;
+use std::env;
+use std::fs;
+use std::io;
+use std::mem;
 
 fn check_parse_res(s: &str, o: &str) {
     let e = parser::parse_str(s).unwrap();
@@ -22,13 +31,20 @@ fn check_parse_kicad(s: &str) {
     assert_eq!(s, t)
 }
 
-fn check_parse_rules(s: &str, rules: formatter::Rules) {
+fn check_parse_rules<R: Into<formatter::RuleSet>>(s: &str, rules: R) {
     let e = parser::parse_str(s).unwrap();
     let t = ser::to_string_with_rules(&e, rules).unwrap();
     assert_eq!(s, t)
 }
 
 
+fn check_pretty(s: &str, width: usize, o: &str) {
+    let e = parser::parse_str(s).unwrap();
+    let f = formatter::PrettyFormatter::new(width);
+    let t = ser::to_string_with_formatter(&e, f).unwrap();
+    assert_eq!(o, t)
+}
+
 fn parse_fail(s: &str) {
     parser::parse_str(s).unwrap();
 }
@@ -154,6 +170,24 @@ fn test_invalid_check_position() {
     )
 }
 
+#[test]
+#[should_panic(expected = "line: 2, col: 7")]
+fn test_invalid_check_position_utf8() {
+    // columns count characters, not bytes
+    parse_fail("(é\n  ü \"x")
+}
+
+#[test]
+fn test_escaped_quote() {
+    let e = parser::parse_str("(\"a\\\"b\" \"c\\\\\" d\" ü\t)").unwrap();
+    let expected = vec![
+        Sexp::String("a\\\"b".into()),
+        Sexp::String("c\\\\\" d".into()),
+        Sexp::String("ü\t".into()),
+    ];
+    assert_eq!(Sexp::List(expected), e);
+}
+
 #[test]
 fn test_complex() {
     check_parse(
@@ -194,6 +228,48 @@ fn test_multiline() {
     )
 }
 
+#[test]
+fn test_rule_set_children() {
+    let rule = formatter::Rule {
+        children_on_own_line: true,
+        close_on_own_line: true,
+        ..Default::default()
+    };
+    let mut rules = formatter::RuleSet::new();
+    rules.insert("module", rule);
+    rules.insert_path(&["module", "fp_text"], rule);
+    check_parse_rules(
+        "\
+(module A
+  (layer F.Cu)
+  (fp_text reference R1
+    (at 0 1)
+    (effects (font (size 1 1)))
+  )
+)",
+        rules,
+    )
+}
+
+#[test]
+fn test_rule_set_items_per_line() {
+    let mut rules = formatter::RuleSet::new();
+    rules.insert(
+        "pts",
+        formatter::Rule {
+            max_items_per_line: Some(3),
+            align: true,
+            ..Default::default()
+        },
+    );
+    check_parse_rules(
+        "\
+(pts (xy 1 2) (xy 3 4) (xy 5 6)
+     (xy 7 8) (xy 9 10))",
+        rules,
+    )
+}
+
 #[test]
 fn test_multiline_two_empty() {
     check_parse_res(
@@ -215,7 +291,692 @@ fn test_fail_pcb() {
     )
 }
 
+#[test]
+fn test_pretty_fits() {
+    check_pretty(
+        "(module A (layer F.Cu) (at 1 2))",
+        32,
+        "(module A (layer F.Cu) (at 1 2))",
+    )
+}
+
+#[test]
+fn test_pretty_breaks() {
+    check_pretty(
+        "(module A (layer F.Cu) (pad 1 smd (at 1 2) (size 0.5 0.5)))",
+        30,
+        "\
+(module A
+  (layer F.Cu)
+  (pad 1 smd
+    (at 1 2)
+    (size 0.5 0.5)))",
+    )
+}
+
+#[test]
+fn test_pretty_nested_fits() {
+    check_pretty(
+        "((a b c d e f) (g h (i j)) \"k l\")",
+        20,
+        "\
+((a b c d e f)
+  (g h (i j)) \"k l\")",
+    )
+}
+
+#[test]
+fn test_pretty_atom() {
+    check_pretty("hello", 2, "hello")
+}
+
+#[test]
+fn test_diff_layout() {
+    let e = parser::parse_str(
+        "(footprint R1 (layer F.Cu) (fp_poly (pts (xy 0 0) (xy 1 0) (xy 1 1) (xy 0 1) \
+         (xy 0 2)) (width 0.1)))",
+    ).unwrap();
+    let f = formatter::DiffLayout::default().formatter();
+    assert_eq!(
+        "\
+(footprint R1
+  (layer F.Cu)
+  (fp_poly
+    (pts (xy 0 0) (xy 1 0) (xy 1 1) (xy 0 1)
+      (xy 0 2)
+    )
+    (width 0.1)
+  )
+)",
+        ser::to_string_with_formatter(&e, f).unwrap()
+    );
+}
+
+#[test]
+fn test_diff_layout_own_line() {
+    let layout = formatter::DiffLayout::new().own_line("pad").wrap("pts", 2);
+    let e = parser::parse_str(
+        "(module A (layer F.Cu) (pad 1 smd (at 1 2)) (pts (xy 1 2) (xy 3 4) (xy 5 6)))",
+    ).unwrap();
+    assert_eq!(
+        "\
+(module A (layer F.Cu)
+  (pad 1 smd (at 1 2))
+  (pts (xy 1 2) (xy 3 4)
+    (xy 5 6)
+  )
+)",
+        ser::to_string_with_formatter(&e, layout.formatter()).unwrap()
+    );
+}
+
+#[test]
+fn test_display_alternate() {
+    let s = "(module A (layer F.Cu) (pad 1 smd (at 1 2) (size 0.5 0.5)))";
+    let e = parser::parse_str(s).unwrap();
+    assert_eq!(s, format!("{}", e));
+    assert_eq!(format!("{}", e), format!("{:#}", e));
+    assert_eq!(
+        "\
+(module A
+  (layer F.Cu)
+  (pad 1 smd
+    (at 1 2)
+    (size 0.5 0.5)))",
+        format!("{:#30}", e)
+    );
+}
+
+#[test]
+fn test_fmt_writer() {
+    use std::fmt::Write as FmtWrite;
+    let e = parser::parse_str("(a \"b c\" (d é))").unwrap();
+    let mut s = String::from("value: ");
+    ser::to_fmt_writer(&mut s, &e).unwrap();
+    write!(s, "; {}", e).unwrap();
+    assert_eq!("value: (a \"b c\" (d é)); (a \"b c\" (d é))", s);
+}
+
+#[test]
+fn test_encode_reused_buffer() {
+    // quoted, reformatted and plain strings share one scratch buffer
+    let e = parser::parse_str("(a \"b c\" 0.50 d \"long e f g\" 1.0 \"\" h)").unwrap();
+    let options = ser::Options::new().floats(float::FloatFormat::Kicad);
+    let f = formatter::Adapter::new(formatter::CompactFormatter);
+    let s = ser::to_string_with_options(&e, f, &options).unwrap();
+    assert_eq!("(a \"b c\" 0.5 d \"long e f g\" 1 \"\" h)", s);
+    let mut out = vec![];
+    {
+        let mut w = ser::SexpWriter::new(&mut out).with_options(options);
+        w.begin_list("a").unwrap();
+        for s in &["b c", "0.50", "d", "1.0", ""] {
+            w.atom(s).unwrap();
+        }
+        w.end_list().unwrap();
+    }
+    assert_eq!("(a \"b c\" 0.5 d 1 \"\")", String::from_utf8(out).unwrap());
+}
+
+// puts nested lists on their own line indented by depth, ')' of the last
+// list of a list on its own line, and uppercases the values of `name`
+struct DepthFormatter;
+
+impl formatter::ContextFormatter for DepthFormatter {
+    fn open<W>(
+        &mut self,
+        writer: &mut W,
+        _context: &formatter::Context,
+        _list: &[Sexp],
+    ) -> Result<(), SexpError>
+    where
+        W: Sink,
+    {
+        writer.write_str("(")
+    }
+
+    fn element<W>(
+        &mut self,
+        writer: &mut W,
+        context: &formatter::Context,
+        value: &Sexp,
+    ) -> Result<(), SexpError>
+    where
+        W: Sink,
+    {
+        if value.is_list() {
+            writer.write_str("\n")?;
+            writer.write_str(&" ".repeat(2 * context.depth()))?;
+        } else {
+            writer.write_str(" ")?;
+        }
+        Ok(())
+    }
+
+    fn close<W>(
+        &mut self,
+        writer: &mut W,
+        context: &formatter::Context,
+        list: &[Sexp],
+    ) -> Result<(), SexpError>
+    where
+        W: Sink,
+    {
+        if list.last().map(|v| v.is_list()).unwrap_or(false) {
+            writer.write_str("\n")?;
+            writer.write_str(&" ".repeat(2 * context.depth()))?;
+        }
+        writer.write_str(")")
+    }
+
+    fn atom<W>(
+        &mut self,
+        writer: &mut W,
+        context: &formatter::Context,
+        _value: &str,
+        text: &str,
+    ) -> Result<(), SexpError>
+    where
+        W: Sink,
+    {
+        if context.parent() == Some("name") && context.index() > 0 {
+            writer.write_str(&text.to_uppercase())?;
+        } else {
+            writer.write_str(text)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_context_formatter() {
+    let e = parser::parse_str("(a (b (name x \"y z\")) (c d))").unwrap();
+    let t = ser::to_string_with_context_formatter(&e, DepthFormatter).unwrap();
+    assert_eq!(
+        "(a\n  (b\n    (name X \"Y Z\")\n  )\n  (c d)\n)",
+        t
+    )
+}
+
+fn check_quoting(options: ser::Options, o: &str) {
+    let e = parser::parse_str("(a \"\" \"b c\" #x x;y %R (layer F.Cu))").unwrap();
+    let f = formatter::Adapter::new(formatter::CompactFormatter);
+    let t = ser::to_string_with_options(&e, f, &options).unwrap();
+    assert_eq!(o, t)
+}
+
+#[test]
+fn test_quoting_policies() {
+    use ser::QuotingPolicy::*;
+    let q = |p| ser::Options::new().quoting(p);
+    check_quoting(q(Default), "(a \"\" \"b c\" #x x;y \"%R\" (layer F.Cu))");
+    check_quoting(q(Minimal), "(a \"\" \"b c\" #x x;y %R (layer F.Cu))");
+    check_quoting(
+        q(Always),
+        "(\"a\" \"\" \"b c\" \"#x\" \"x;y\" \"%R\" (\"layer\" \"F.Cu\"))",
+    );
+    check_quoting(q(KicadLegacy), "(a \"\" \"b c\" \"#x\" x;y %R (layer F.Cu))");
+    check_quoting(q(Kicad), "(a \"\" \"b c\" \"#x\" x;y %R (layer \"F.Cu\"))");
+    check_quoting(q(Lisp), "(a \"\" \"b c\" #x \"x;y\" %R (layer F.Cu))");
+}
+
+#[test]
+fn test_quoting_embedded_quotes() {
+    use ser::QuotingPolicy::*;
+    let atoms = ["a", "\"x", "b\"c d", "e\\\" f"];
+    let e = Sexp::List(atoms.iter().map(|&s| s.into()).collect());
+    let write = |e: &Sexp, options: &ser::Options| {
+        let f = formatter::Adapter::new(formatter::CompactFormatter);
+        ser::to_string_with_options(e, f, options).unwrap()
+    };
+    for &policy in &[Default, Minimal, Always, Kicad, Lisp] {
+        let options = ser::Options::new().quoting(policy);
+        let t = write(&e, &options);
+        let parsed = parser::parse_str(&t).unwrap();
+        // escape sequences are kept, so a parsed string is parsed back the same
+        assert_eq!(parsed, parser::parse_str(&write(&parsed, &options)).unwrap());
+        let parsed = parsed.list().unwrap();
+        assert_eq!(atoms.len(), parsed.len(), "{:?}: {}", policy, t);
+        assert_eq!("b\\\"c d", parsed[2].string().unwrap());
+        assert_eq!("e\\\" f", parsed[3].string().unwrap());
+    }
+    let t = write(&e, &ser::Options::new().quoting(Minimal));
+    assert_eq!("(a \"\\\"x\" \"b\\\"c d\" \"e\\\" f\")", t);
+}
+
+#[test]
+fn test_quoting_override() {
+    let options = ser::Options::new()
+        .quoting(ser::QuotingPolicy::Always)
+        .quote_with(|c, _| if c.index() == 0 { Some(false) } else { None });
+    check_quoting(options, "(a \"\" \"b c\" \"#x\" \"x;y\" \"%R\" (layer \"F.Cu\"))");
+}
+
+#[test]
+fn test_options_send_sync() {
+    let options = ser::Options::new().quote_with(|_, s| Some(s.contains('.')));
+    let t = ::std::thread::spawn(move || {
+        check_quoting(options, "(a  b c #x x;y %R (layer \"F.Cu\"))")
+    });
+    t.join().unwrap();
+}
+
+#[test]
+fn test_field_formats() {
+    use ser::{Field, FieldFormat};
+    let e = parser::parse_str(
+        "(footprint R (property Reference R1) (uuid 1234) (pad 1 smd (at 1 2.5) \
+         (size 0.5 0.5)))",
+    ).unwrap();
+    let options = ser::Options::new()
+        .quote_with(|_, _| Some(false))
+        .field(Field::index("property", 1), FieldFormat::quoted())
+        .field(Field::index("property", 2), FieldFormat::quoted())
+        .field(Field::index("uuid", 1), FieldFormat::quoted())
+        .field(Field::child("pad", "at"), FieldFormat::number(float::FloatFormat::Fixed(2)))
+        .field(Field::child("pad", "size"), FieldFormat::number(float::FloatFormat::Fixed(3)))
+        .field(Field::index("size", 2), FieldFormat::quoted());
+    let f = formatter::Adapter::new(formatter::CompactFormatter);
+    assert_eq!(
+        "(footprint R (property \"Reference\" \"R1\") (uuid \"1234\") \
+         (pad 1 smd (at 1.00 2.50) (size 0.500 \"0.5\")))",
+        ser::to_string_with_options(&e, f, &options).unwrap()
+    );
+}
+
+fn check_whitespace<F: formatter::ContextFormatter>(f: F, o: &str) {
+    let s = "(module A (layer F.Cu) (pad 1 smd (at 1 2) (size 0.5 0.5)))";
+    let e = parser::parse_str(s).unwrap();
+    let options = ser::Options::new()
+        .indent("\t")
+        .line_ending(formatter::LineEnding::CrLf)
+        .final_newline(true);
+    assert_eq!(o, ser::to_string_with_options(&e, f, &options).unwrap());
+}
+
+#[test]
+fn test_whitespace() {
+    check_whitespace(
+        formatter::Adapter::new(formatter::CompactFormatter),
+        "(module A (layer F.Cu) (pad 1 smd (at 1 2) (size 0.5 0.5)))\r\n",
+    );
+    check_whitespace(
+        formatter::Adapter::new(formatter::PrettyFormatter::new(30)),
+        "(module A\r\n\t(layer F.Cu)\r\n\t(pad 1 smd\r\n\t\t(at 1 2)\r\n\t\t(size 0.5 0.5)))\r\n",
+    );
+    check_whitespace(
+        formatter::Adapter::new(formatter::RulesFormatter::new(kicad_test_rules())),
+        "(module A\r\n\t(layer F.Cu)\r\n\t(pad 1 smd (at 1 2) (size 0.5 0.5)))\r\n",
+    );
+    check_whitespace(
+        formatter::Adapter::new(kicad::KicadFormatter::new(kicad::KicadVersion::Legacy)),
+        "(module A (layer F.Cu)\r\n\t(pad 1 smd (at 1 2) (size 0.5 0.5))\r\n)\r\n",
+    );
+}
+
+fn stream<F: formatter::Formatter>(w: &mut ser::SexpWriter<Vec<u8>, F>) {
+    w.begin_list("module").unwrap();
+    w.atom("A").unwrap();
+    w.begin_list("layer").unwrap();
+    w.atom("F.Cu").unwrap();
+    w.end_list().unwrap();
+    w.begin_unnamed_list().unwrap();
+    w.begin_list("x").unwrap();
+    w.end_list().unwrap();
+    w.atom("y").unwrap();
+    w.end_list().unwrap();
+    w.begin_unnamed_list().unwrap();
+    w.end_list().unwrap();
+    w.begin_list("pad").unwrap();
+    w.number(1).unwrap();
+    w.atom("smd").unwrap();
+    w.begin_list("at").unwrap();
+    w.number(1.0).unwrap();
+    w.number(2.5).unwrap();
+    w.end_list().unwrap();
+    w.end_list().unwrap();
+    w.end_list().unwrap();
+}
+
+fn check_stream<F: formatter::Formatter>(f1: F, f2: F) {
+    let e = parser::parse_str("(module A (layer F.Cu) ((x) y) () (pad 1 smd (at 1 2.5)))").unwrap();
+    let mut w = ser::SexpWriter::with_formatter(vec![], f1);
+    stream(&mut w);
+    let t = String::from_utf8(w.finish().unwrap()).unwrap();
+    assert_eq!(ser::to_string_with_formatter(&e, f2).unwrap(), t)
+}
+
+#[test]
+fn test_sexp_writer() {
+    check_stream(formatter::CompactFormatter, formatter::CompactFormatter);
+    check_stream(
+        formatter::RulesFormatter::new(kicad_test_rules()),
+        formatter::RulesFormatter::new(kicad_test_rules()),
+    );
+    check_stream(formatter::PrettyFormatter::new(20), formatter::PrettyFormatter::new(20));
+}
+
+#[test]
+fn test_sexp_writer_value() {
+    let e = parser::parse_file("examples/SILABS_EFM32_QFN24.kicad_mod").unwrap();
+    let f = formatter::RulesFormatter::new(kicad_test_rules());
+    let mut w = ser::SexpWriter::with_formatter(vec![], f);
+    w.value(&e).unwrap();
+    let t = String::from_utf8(w.finish().unwrap()).unwrap();
+    assert_eq!(ser::to_string_with_rules(&e, kicad_test_rules()).unwrap(), t)
+}
+
+#[test]
+fn test_sexp_writer_balance() {
+    let mut w = ser::SexpWriter::new(vec![]);
+    assert!(w.end_list().is_err());
+    w.begin_list("a").unwrap();
+    w.begin_list("b").unwrap();
+    w.end_list().unwrap();
+    assert!(ser::SexpWriter::new(vec![]).finish().is_ok());
+    assert!(w.finish().is_err());
+    let mut w = ser::SexpWriter::new(vec![]);
+    w.atom("a").unwrap();
+    assert!(w.atom("b").is_err());
+}
+
+#[test]
+fn test_context() {
+    let heads = ["a", "b"];
+    let c = formatter::Context::new(&heads, 2, 3);
+    assert_eq!(2, c.depth());
+    assert_eq!(Some("b"), c.parent());
+    assert_eq!(3, c.siblings());
+    assert!(c.is_last());
+}
+
 #[test]
 fn test_quoted_escape() {
     check_parse_kicad("(hello \"21\\\" (inch test)\")")
 }
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_parallel() {
+    let s = parser::read_file("examples/SILABS_EFM32_QFN24.kicad_mod").unwrap();
+    let e = parser::parse_str(&s).unwrap();
+    assert_eq!(e, parser::parse_str_parallel(&s).unwrap());
+    for s in &["", "hello", "()", "(a (b c) \"d e\" (f))"] {
+        assert_eq!(parser::parse_str(s).unwrap(), parser::parse_str_parallel(s).unwrap());
+    }
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+#[should_panic(expected = "line: 3, col: 9")]
+fn test_parallel_error_position() {
+    parser::parse_str_parallel("(a\n  (b c)\n  (d \"e)").unwrap();
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn test_parse_file_mmap() {
+    let name = "examples/SILABS_EFM32_QFN24.kicad_mod";
+    let e = parser::parse_file(name).unwrap();
+    assert_eq!(e, parser::parse_file_mmap(name).unwrap());
+}
+
+#[test]
+fn test_file_roundtrip() {
+    let name = env::temp_dir().join("symbolic_expressions_test_roundtrip.kicad_mod");
+    let name = name.to_str().unwrap();
+    let e = parser::parse_file("examples/SILABS_EFM32_QFN24.kicad_mod").unwrap();
+    ser::to_file(name, &e).unwrap();
+    assert_eq!(e, parser::parse_file(name).unwrap());
+    fs::remove_file(name).unwrap();
+}
+
+// fails when the list `fail` is written
+struct FailFormatter;
+
+impl formatter::Formatter for FailFormatter {
+    fn open<W>(&mut self, writer: &mut W, value: Option<&Sexp>) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        match value {
+            Some(Sexp::String(s)) if s == "fail" => Err("failed".into()),
+            _ => formatter::CompactFormatter.open(writer, value),
+        }
+    }
+
+    fn element<W>(&mut self, writer: &mut W, value: &Sexp) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        formatter::CompactFormatter.element(writer, value)
+    }
+
+    fn close<W>(&mut self, writer: &mut W) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        formatter::CompactFormatter.close(writer)
+    }
+}
+
+// writes lists in brackets with the bytes it is given
+struct BracketFormatter(&'static [u8]);
+
+impl formatter::Formatter for BracketFormatter {
+    fn open<W>(&mut self, writer: &mut W, _value: Option<&Sexp>) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        writer.write_all(b"[")?;
+        Ok(())
+    }
+
+    fn element<W>(&mut self, writer: &mut W, _value: &Sexp) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        writer.write_all(self.0)?;
+        Ok(())
+    }
+
+    fn close<W>(&mut self, writer: &mut W) -> Result<(), SexpError>
+    where
+        W: io::Write,
+    {
+        write!(writer, "]")?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_io_write_formatter() {
+    let e = parser::parse_str("(a (b \"c d\"))").unwrap();
+    let s = ser::to_string_with_formatter(&e, BracketFormatter(b" ")).unwrap();
+    assert_eq!("[a [b \"c d\"]]", s);
+    let v = ser::to_vec_with_formatter(&e, BracketFormatter(b" ")).unwrap();
+    assert_eq!(b"[a [b \"c d\"]]", &v[..]);
+    let mut s = String::new();
+    ser::to_fmt_writer_with_formatter(&mut s, BracketFormatter("\u{a0}".as_bytes()), &e).unwrap();
+    assert_eq!("[a\u{a0}[b\u{a0}\"c d\"]]", s);
+    // a String can't hold what isn't UTF-8, a Vec can
+    assert!(ser::to_string_with_formatter(&e, BracketFormatter(b"\xff")).is_err());
+    let v = ser::to_vec_with_formatter(&e, BracketFormatter(b"\xff")).unwrap();
+    assert_eq!(b"[a\xff[b\xff\"c d\"]]", &v[..]);
+}
+
+#[test]
+fn test_file_atomic() {
+    let dir = env::temp_dir().join("symbolic_expressions_test_atomic");
+    fs::create_dir_all(&dir).unwrap();
+    let name = dir.join("a.sexp");
+    let name = name.to_str().unwrap();
+    ser::to_file(name, &parser::parse_str("(a 1)").unwrap()).unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(name, fs::Permissions::from_mode(0o640)).unwrap();
+    }
+    let e = parser::parse_str("(a (fail))").unwrap();
+    assert!(ser::to_file_with_formatter(name, FailFormatter, &e).is_err());
+    assert_eq!("(a 1)", fs::read_to_string(name).unwrap());
+    let options = ser::Options::new().backup(true);
+    let f = formatter::Adapter::new(formatter::CompactFormatter);
+    ser::to_file_with_options(name, f, &options, &parser::parse_str("(a 2)").unwrap()).unwrap();
+    assert_eq!("(a 2)", fs::read_to_string(name).unwrap());
+    assert_eq!("(a 1)", fs::read_to_string(format!("{}.bak", name)).unwrap());
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(0o640, fs::metadata(name).unwrap().permissions().mode() & 0o777);
+    }
+    assert_eq!(2, fs::read_dir(&dir).unwrap().count());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_file_threads() {
+    let dir = env::temp_dir().join("symbolic_expressions_test_threads");
+    fs::create_dir_all(&dir).unwrap();
+    let name = dir.join("a.sexp");
+    let threads: Vec<_> = (0..8)
+        .map(|i| {
+            let name = name.clone();
+            ::std::thread::spawn(move || {
+                let e = parser::parse_str(&format!("(a {})", i)).unwrap();
+                for _ in 0..20 {
+                    ser::to_file(name.to_str().unwrap(), &e).unwrap();
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+    // one complete file and no temporary files left behind
+    let e = parser::parse_file(name.to_str().unwrap()).unwrap();
+    assert_eq!("a", e.list_name().unwrap());
+    assert_eq!(1, fs::read_dir(&dir).unwrap().count());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(not(feature = "gzip"))]
+#[test]
+#[should_panic(expected = "enable the `gzip` feature")]
+fn test_file_gzip_disabled() {
+    let name = env::temp_dir().join("symbolic_expressions_test_disabled.kicad_mod.gz");
+    let e = parser::parse_str("(a)").unwrap();
+    ser::to_file(name.to_str().unwrap(), &e).unwrap();
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_file_gzip() {
+    check_compressed_file("symbolic_expressions_test.kicad_mod.gz", &[0x1f, 0x8b])
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_file_zstd() {
+    check_compressed_file("symbolic_expressions_test.kicad_mod.zst", &[0x28, 0xb5, 0x2f, 0xfd])
+}
+
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+fn check_compressed_file(name: &str, magic: &[u8]) {
+    let name = env::temp_dir().join(name);
+    let name = name.to_str().unwrap();
+    let e = parser::parse_file("examples/SILABS_EFM32_QFN24.kicad_mod").unwrap();
+    ser::to_file(name, &e).unwrap();
+    assert!(fs::read(name).unwrap().starts_with(magic));
+    assert_eq!(e, parser::parse_file(name).unwrap());
+    // detection on parsing is done on the content, not the name
+    let renamed = format!("{}.renamed", name);
+    fs::rename(name, &renamed).unwrap();
+    assert_eq!(e, parser::parse_file(&renamed).unwrap());
+    fs::remove_file(renamed).unwrap();
+}
+
+#[test]
+fn test_canonical() {
+    let data = b"(3:abc[10:text/plain]5:hello4:\xff\x00\x01\x02()0:)";
+    let e = parser::parse_canonical(data).unwrap();
+    let hello = Bytes {
+        hint: Some(b"text/plain".to_vec()),
+        data: b"hello".to_vec(),
+    };
+    let binary = Bytes {
+        hint: None,
+        data: vec![0xff, 0, 1, 2],
+    };
+    let expected = vec![
+        "abc".into(),
+        hello.into(),
+        binary.into(),
+        Sexp::List(vec![]),
+        "".into(),
+    ];
+    assert_eq!(Sexp::List(expected), e);
+    assert_eq!(&data[..], &ser::to_canonical(&e).unwrap()[..]);
+    assert_eq!("(abc [text/plain]hello #ff000102# () \"\")", e.to_string());
+    assert_eq!(Sexp::Empty, parser::parse_canonical(b"").unwrap());
+}
+
+#[test]
+fn test_sexp_size() {
+    // `Bytes` is boxed, so a `Sexp` is a `String` or `Vec` and a tag
+    assert!(mem::size_of::<Sexp>() <= mem::size_of::<String>() + mem::size_of::<usize>());
+}
+
+#[test]
+fn test_canonical_errors() {
+    let error = |data: &[u8]| parser::parse_canonical(data).unwrap_err().to_string();
+    assert!(error(b"(3:ab").contains("End of file reached"));
+    assert!(error(b"(03:abc)").contains("leading zero"));
+    assert!(error(b"(3abc)").contains("line: 1, col: 3"));
+    assert!(error(b"3:abc)").contains("Trailing data"));
+    assert!(error(b"([3:abc3:def)").contains("expected ]"));
+    assert!(error(b"(abc)").contains("expected the length"));
+    assert!(error(b"(99999999999999999999999:a)").contains("End of file reached"));
+    assert!(error(&[b'('; 1_000_000]).contains("nested too deep"));
+}
+
+#[test]
+fn test_canonical_depth() {
+    let nested = |depth: usize| {
+        let mut e = Sexp::String("a".into());
+        for _ in 0..depth {
+            e = Sexp::List(vec![e]);
+        }
+        e
+    };
+    let e = nested(parser::MAX_CANONICAL_DEPTH);
+    let data = ser::to_canonical(&e).unwrap();
+    assert_eq!(e, parser::parse_canonical(&data).unwrap());
+    let e = nested(parser::MAX_CANONICAL_DEPTH + 1);
+    assert!(ser::to_canonical(&e).unwrap_err().to_string().contains("nested too deep"));
+    let mut data = vec![b'('];
+    data.extend(ser::to_canonical(&nested(parser::MAX_CANONICAL_DEPTH)).unwrap());
+    data.push(b')');
+    assert!(parser::parse_canonical(&data).unwrap_err().to_string().contains("nested too deep"));
+}
+
+#[test]
+fn test_pretty_fill_atoms() {
+    let e = parser::parse_str("(e f g h i j k l m n o p q r s t u v w x y z)").unwrap();
+    assert_eq!(
+        "(e f g h i\n  j k l m\n  n o p q\n  r s t u\n  v w x y\n  z)",
+        format!("{:#10}", e)
+    );
+}
+
+#[test]
+fn test_pretty_trailing_close() {
+    // (at 1 2) fits in 14 columns by itself, but not with the parens that close its parents
+    check_pretty(
+        "(a (b (at 1 2)))",
+        14,
+        "\
+(a
+  (b
+    (at 1 2)))",
+    )
+}
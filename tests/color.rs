@@ -0,0 +1,62 @@
+// (c) 2017 Productize SPRL <joost@productize.be>
+
+extern crate symbolic_expressions;
+
+use symbolic_expressions::color::{ColorFormatter, Colors};
+use symbolic_expressions::{parser, ser, CompactFormatter, PrettyFormatter};
+
+#[test]
+fn test_color() {
+    let e = parser::parse_str("(at 1.5 \"a b\" x)").unwrap();
+    let s = ser::to_string_with_formatter(&e, ColorFormatter::new(CompactFormatter)).unwrap();
+    assert_eq!(
+        "\x1b[2m(\x1b[0m\x1b[1;34mat\x1b[0m \x1b[36m1.5\x1b[0m \
+         \x1b[32m\"a b\"\x1b[0m x\x1b[2m)\x1b[0m",
+        s
+    );
+}
+
+#[test]
+fn test_color_rainbow() {
+    let e = parser::parse_str("(a (b))").unwrap();
+    let colors = Colors {
+        head: "",
+        rainbow: true,
+        ..Default::default()
+    };
+    let f = ColorFormatter::new(CompactFormatter).with_colors(colors);
+    assert_eq!(
+        "\x1b[33m(\x1b[0ma \x1b[35m(\x1b[0mb\x1b[35m)\x1b[0m\x1b[33m)\x1b[0m",
+        ser::to_string_with_formatter(&e, f).unwrap()
+    );
+}
+
+#[test]
+fn test_color_keeps_layout() {
+    let e = parser::parse_str("(module A (layer F.Cu) (pad 1 smd (at 1 2) (size 0.5 0.5)) \"x\")")
+        .unwrap();
+    let plain = ser::to_string_with_formatter(&e, PrettyFormatter::new(30)).unwrap();
+    let f = ColorFormatter::new(PrettyFormatter::new(30));
+    let colored = ser::to_string_with_formatter(&e, f).unwrap();
+    assert_ne!(plain, colored);
+    let mut stripped = String::new();
+    let mut chars = colored.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|&c| c == 'm');
+        } else {
+            stripped.push(c);
+        }
+    }
+    assert_eq!(plain, stripped);
+}
+
+#[test]
+fn test_color_disabled() {
+    let e = parser::parse_str("(at 1 2)").unwrap();
+    let f = ColorFormatter::new(CompactFormatter).with_enabled(false);
+    assert_eq!("(at 1 2)", ser::to_string_with_formatter(&e, f).unwrap());
+    let e = parser::parse_str("42").unwrap();
+    let f = ColorFormatter::new(CompactFormatter);
+    assert_eq!("\x1b[36m42\x1b[0m", ser::to_string_with_formatter(&e, f).unwrap());
+}
@@ -0,0 +1,105 @@
+// (c) 2017 Productize SPRL <joost@productize.be>
+
+extern crate symbolic_expressions;
+
+use std::fs;
+
+use symbolic_expressions::edit::Document;
+use symbolic_expressions::{parser, Sexp};
+
+const LEGACY: &str = "tests/data/R_0603_1608Metric_legacy.kicad_mod";
+const MODERN: &str = "tests/data/R_0603_1608Metric_modern.kicad_mod";
+
+#[test]
+fn test_edit_roundtrip() {
+    for name in &[LEGACY, MODERN, "tests/data/divider_modern.kicad_sch"] {
+        let s = fs::read_to_string(name).unwrap();
+        let doc = Document::parse(&s).unwrap();
+        assert_eq!(s, doc.to_string());
+        assert_eq!(parser::parse_str(&s).unwrap(), doc.to_sexp().unwrap());
+    }
+}
+
+#[test]
+fn test_edit_set() {
+    let s = fs::read_to_string(MODERN).unwrap();
+    let mut doc = Document::load(MODERN).unwrap();
+    {
+        let reference = doc.root_mut().find_mut("property").unwrap();
+        assert_eq!(Some("\"Reference\""), reference.get(1).map(|n| n.to_string()).as_deref());
+        let at = reference.find_mut("at").unwrap();
+        at.get_mut(2).unwrap().set(&"-1.5".into()).unwrap();
+    }
+    let out = doc.to_string();
+    let changed: Vec<_> = s.lines().zip(out.lines()).filter(|&(a, b)| a != b).collect();
+    assert_eq!(vec![("\t\t(at 0 -1.43 0)", "\t\t(at 0 -1.5 0)")], changed);
+    assert_eq!(s.lines().count(), out.lines().count());
+}
+
+#[test]
+fn test_edit_insert_remove() {
+    let mut doc = Document::parse("(module R1\n  (layer F.Cu)\n  (at 1 2)\n)\n").unwrap();
+    let tags: Sexp = vec!["tags".into(), "resistor".into()].into();
+    doc.root_mut().push(&tags).unwrap();
+    assert_eq!("(module R1\n  (layer F.Cu)\n  (at 1 2)\n  (tags resistor)\n)\n", doc.to_string());
+    doc.root_mut().insert(2, &"R 1".into()).unwrap();
+    doc.get_mut(&[4]).unwrap().push(&"90".into()).unwrap();
+    assert_eq!(
+        "(module R1\n  \"R 1\"\n  (layer F.Cu)\n  (at 1 2 90)\n  (tags resistor)\n)\n",
+        doc.to_string()
+    );
+    doc.root_mut().remove(2).unwrap();
+    doc.root_mut().remove(3).unwrap();
+    assert_eq!("(module R1\n  (layer F.Cu)\n  (tags resistor)\n)\n", doc.to_string());
+    doc.get_mut(&[2]).unwrap().remove(0).unwrap();
+    assert_eq!("(module R1\n  (F.Cu)\n  (tags resistor)\n)\n", doc.to_string());
+    assert_eq!(Some("tags"), doc.get(&[3]).and_then(|n| n.name()));
+}
+
+#[test]
+fn test_edit_remove_last() {
+    let mut doc = Document::parse("(a\n  b\n)").unwrap();
+    doc.root_mut().remove(1).unwrap();
+    assert_eq!("(a)", doc.to_string());
+    let mut doc = Document::parse("(a\n  b\n)").unwrap();
+    doc.root_mut().remove(0).unwrap();
+    assert_eq!("(b)", doc.to_string());
+    let mut doc = Document::parse("(\n  a\n  b\n  c\n)").unwrap();
+    doc.root_mut().remove(2).unwrap();
+    assert_eq!("(\n  a\n  b\n)", doc.to_string());
+    // the only element
+    let mut doc = Document::parse("( a\n)").unwrap();
+    doc.root_mut().remove(0).unwrap();
+    assert_eq!("()", doc.to_string());
+    doc.root_mut().push(&"b".into()).unwrap();
+    assert_eq!("(b)", doc.to_string());
+}
+
+#[test]
+fn test_edit_quoted() {
+    let mut doc = Document::parse("(kicad_sch\n  (generator_version 7.0)\n)").unwrap();
+    {
+        let version = doc.root_mut().find_mut("generator_version").unwrap();
+        version.get_mut(1).unwrap().set_quoted("8.0").unwrap();
+        version.insert_quoted(2, "a \"b\"").unwrap();
+    }
+    assert_eq!(
+        "(kicad_sch\n  (generator_version \"8.0\" \"a \\\"b\\\"\")\n)",
+        doc.to_string()
+    );
+    let version = doc.root().find("generator_version").unwrap();
+    assert_eq!(Some("8.0"), version.get(1).and_then(|n| n.name()));
+    assert!(doc.get_mut(&[1, 1]).unwrap().insert_quoted(0, "x").is_err());
+}
+
+#[test]
+fn test_edit_errors() {
+    assert!(Document::parse("").is_err());
+    assert!(Document::parse("(a (b)").is_err());
+    let mut doc = Document::parse("(a b)").unwrap();
+    assert!(doc.root_mut().insert(3, &"c".into()).is_err());
+    assert!(doc.root_mut().remove(2).is_err());
+    assert!(doc.get_mut(&[1]).unwrap().push(&"c".into()).is_err());
+    assert!(doc.root_mut().push(&Sexp::Empty).is_err());
+    assert!(doc.get(&[1, 0]).is_none());
+}
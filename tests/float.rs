@@ -0,0 +1,45 @@
+// (c) 2017 Productize SPRL <joost@productize.be>
+
+extern crate symbolic_expressions;
+
+use symbolic_expressions::float::FloatFormat;
+use symbolic_expressions::{parser, ser, Adapter, CompactFormatter, Sexp};
+
+#[test]
+fn test_float_formats() {
+    let f = 0.1 + 0.2;
+    assert_eq!("0.30000000000000004", FloatFormat::Shortest.format(f));
+    assert_eq!("0.300", FloatFormat::Fixed(3).format(f));
+    assert_eq!("0.3", FloatFormat::Trimmed(3).format(f));
+    assert_eq!("0.3", FloatFormat::Kicad.format(f));
+    assert_eq!("1", FloatFormat::Kicad.format(1.0));
+    assert_eq!("0", FloatFormat::Trimmed(2).format(-0.001));
+    assert_eq!("0.00000001", FloatFormat::Kicad.format(1e-8));
+    assert_eq!("100000000000000000000", FloatFormat::Shortest.format(1e20));
+}
+
+#[test]
+fn test_float_sexp() {
+    let s: Sexp = 1.5.into();
+    assert_eq!("1.5", s.string().unwrap());
+    assert_eq!("1.50", FloatFormat::Fixed(2).to_sexp(1.5).string().unwrap());
+}
+
+#[test]
+fn test_float_serializer() {
+    let e = parser::parse_str("(at 1e-7 0.30000000000000004 -2.50 1 pad.1)").unwrap();
+    let options = ser::Options::new().floats(FloatFormat::Kicad);
+    let s = ser::to_string_with_options(&e, Adapter::new(CompactFormatter), &options).unwrap();
+    assert_eq!("(at 0.0000001 0.3 -2.5 1 pad.1)", s);
+}
+
+#[test]
+fn test_float_serializer_quoted() {
+    let s = "(kicad_sch (version 20231120) (generator_version \"8.0\") (at 1.0 2))";
+    let e = parser::parse_str(s).unwrap();
+    let options = ser::Options::new()
+        .quoting(ser::QuotingPolicy::Kicad)
+        .floats(FloatFormat::Kicad);
+    let s = ser::to_string_with_options(&e, Adapter::new(CompactFormatter), &options).unwrap();
+    assert_eq!("(kicad_sch (version 20231120) (generator_version \"8.0\") (at 1 2))", s);
+}
@@ -0,0 +1,114 @@
+// (c) 2017 Productize SPRL <joost@productize.be>
+
+extern crate symbolic_expressions;
+
+use std::env;
+use std::fs;
+use std::io::Cursor;
+use std::time::{Duration, SystemTime};
+
+use symbolic_expressions::index::Index;
+use symbolic_expressions::parser;
+
+const PCB: &str = "(kicad_pcb (version 4)
+  (module A (layer F.Cu) (pad 1 smd rect))
+  (module \"B C\" (layer B.Cu))
+  ((anonymous)) (module D))";
+
+#[test]
+fn test_index_entries() {
+    let i = Index::build(PCB).unwrap();
+    let names: Vec<_> = i.entries().iter().map(|e| e.name.clone()).collect();
+    assert_eq!(names[0], Some("kicad_pcb".into()));
+    assert_eq!(names[1], Some("version".into()));
+    assert_eq!(names[2], Some("module".into()));
+    assert_eq!(i.entries()[3].depth, 2);
+    assert_eq!(i.entries().iter().filter(|e| e.name.is_none()).count(), 1);
+    let m: Vec<_> = i.named("module").map(|e| &PCB[e.start..e.end]).collect();
+    assert_eq!(
+        m,
+        vec![
+            "(module A (layer F.Cu) (pad 1 smd rect))",
+            "(module \"B C\" (layer B.Cu))",
+            "(module D)",
+        ]
+    );
+}
+
+#[test]
+fn test_index_parse_entry() {
+    let i = Index::build(PCB).unwrap();
+    let e = i.named("module").nth(1).unwrap();
+    let s = e.parse(&mut Cursor::new(PCB)).unwrap();
+    assert_eq!(s, parser::parse_str("(module \"B C\" (layer B.Cu))").unwrap());
+}
+
+#[test]
+fn test_index_save_load() {
+    let dir = env::temp_dir();
+    let source = dir.join("symbolic_expressions_test_index.kicad_pcb");
+    let index = dir.join("symbolic_expressions_test_index.idx");
+    let source = source.to_str().unwrap();
+    let index = index.to_str().unwrap();
+    fs::write(source, PCB).unwrap();
+    let _ = fs::remove_file(index);
+    let built = Index::load_or_build(source, index).unwrap();
+    let loaded = Index::load(index).unwrap();
+    assert_eq!(built, loaded);
+    let e = loaded.named("pad").next().unwrap();
+    assert_eq!(e.parse_file(source).unwrap().list_name().unwrap(), "pad");
+    fs::remove_file(source).unwrap();
+    fs::remove_file(index).unwrap();
+}
+
+#[test]
+fn test_index_rebuild_same_size() {
+    let dir = env::temp_dir();
+    let source = dir.join("symbolic_expressions_test_index_edit.kicad_pcb");
+    let index = dir.join("symbolic_expressions_test_index_edit.idx");
+    let source = source.to_str().unwrap();
+    let index = index.to_str().unwrap();
+    fs::write(source, PCB).unwrap();
+    let _ = fs::remove_file(index);
+    Index::load_or_build(source, index).unwrap();
+    // same size, so only the hash tells the index is stale
+    let edited = PCB.replace("(pad 1 smd rect)", "(via 1 smd rect)");
+    fs::write(source, &edited).unwrap();
+    let rebuilt = Index::load_or_build(source, index).unwrap();
+    assert_eq!(rebuilt.source_len(), PCB.len());
+    assert_ne!(rebuilt.source_hash(), Index::build(PCB).unwrap().source_hash());
+    assert_eq!(rebuilt.named("via").count(), 1);
+    assert_eq!(Index::load(index).unwrap(), rebuilt);
+    fs::remove_file(source).unwrap();
+    fs::remove_file(index).unwrap();
+}
+
+#[test]
+fn test_index_file_stamp() {
+    let dir = env::temp_dir();
+    let source = dir.join("symbolic_expressions_test_index_stamp.kicad_pcb");
+    let index = dir.join("symbolic_expressions_test_index_stamp.idx");
+    let source = source.to_str().unwrap();
+    let index = index.to_str().unwrap();
+    let set_modified = |secs: u64| {
+        let f = fs::File::options().write(true).open(source).unwrap();
+        f.set_modified(SystemTime::now() - Duration::from_secs(secs)).unwrap();
+    };
+    fs::write(source, PCB).unwrap();
+    set_modified(3600);
+    let _ = fs::remove_file(index);
+    Index::load_or_build(source, index).unwrap();
+    // the same size and modification time, so the text isn't looked at
+    let edited = PCB.replace("(pad 1 smd rect)", "(via 1 smd rect)");
+    let modified = fs::metadata(source).unwrap().modified().unwrap();
+    fs::write(source, &edited).unwrap();
+    fs::File::options().write(true).open(source).unwrap().set_modified(modified).unwrap();
+    assert_eq!(Index::load_or_build(source, index).unwrap().named("via").count(), 0);
+    // a different modification time, so the text is hashed again
+    set_modified(1800);
+    let rebuilt = Index::load_or_build(source, index).unwrap();
+    assert_eq!(rebuilt.named("via").count(), 1);
+    assert_eq!(Index::load(index).unwrap(), rebuilt);
+    fs::remove_file(source).unwrap();
+    fs::remove_file(index).unwrap();
+}
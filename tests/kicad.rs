@@ -0,0 +1,88 @@
+// (c) 2017 Productize SPRL <joost@productize.be>
+
+extern crate symbolic_expressions;
+
+use std::fs;
+
+use symbolic_expressions::kicad::{KicadFormatter, KicadVersion};
+use symbolic_expressions::{parser, ser};
+
+fn check_roundtrip(name: &str, version: KicadVersion) {
+    let s = fs::read_to_string(name).unwrap();
+    let e = parser::parse_str(&s).unwrap();
+    let out = ser::to_string_kicad(&e, version).unwrap();
+    assert_eq!(s, out);
+}
+
+// the samples in tests/data are written by hand in the layout of KiCad 5,
+// KiCad 6 and 7 and KiCad 8 and keep the formatter from changing by
+// accident; only files saved by KiCad itself show that it matches KiCad
+
+#[test]
+fn test_legacy_footprint() {
+    check_roundtrip("tests/data/R_0603_1608Metric_legacy.kicad_mod", KicadVersion::Legacy)
+}
+
+#[test]
+fn test_v6_footprint() {
+    check_roundtrip("tests/data/R_0603_1608Metric_v6.kicad_mod", KicadVersion::V6)
+}
+
+#[test]
+fn test_v6_schematic() {
+    check_roundtrip("tests/data/divider_v6.kicad_sch", KicadVersion::V6)
+}
+
+#[test]
+fn test_modern_footprint() {
+    check_roundtrip("tests/data/R_0603_1608Metric_modern.kicad_mod", KicadVersion::Modern)
+}
+
+#[test]
+fn test_modern_schematic() {
+    check_roundtrip("tests/data/divider_modern.kicad_sch", KicadVersion::Modern)
+}
+
+// files saved by KiCad itself go in tests/data/saved/kicad5, kicad6, kicad7
+// and kicad8, and have to come out unchanged
+#[test]
+fn test_saved_files() {
+    let versions = [
+        ("kicad5", KicadVersion::Legacy),
+        ("kicad6", KicadVersion::V6),
+        ("kicad7", KicadVersion::V6),
+        ("kicad8", KicadVersion::Modern),
+    ];
+    for &(dir, version) in &versions {
+        let entries = match fs::read_dir(format!("tests/data/saved/{}", dir)) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries {
+            let path = entry.unwrap().path();
+            check_roundtrip(path.to_str().unwrap(), version);
+        }
+    }
+}
+
+#[test]
+fn test_kicad_numbers() {
+    let s = "(module x (tedit 12345E67) (at 0.0 -1.50) (size 1e-7 0.30000000000000004))";
+    let e = parser::parse_str(s).unwrap();
+    let s = ser::to_string_kicad(&e, KicadVersion::Legacy).unwrap();
+    assert_eq!("(module x (tedit 12345E67)\n  (at 0 -1.5)\n  (size 0.0000001 0.3)\n)\n", s);
+    let e = parser::parse_str("(footprint x (property \"Value\" \"1.50\" (at 1.0 2)))").unwrap();
+    let s = ser::to_string_kicad(&e, KicadVersion::V6).unwrap();
+    assert_eq!("(footprint \"x\"\n  (property \"Value\" \"1.50\" (at 1 2))\n)\n", s);
+}
+
+#[test]
+fn test_modern_streaming() {
+    let s = fs::read_to_string("tests/data/divider_modern.kicad_sch").unwrap();
+    let e = parser::parse_str(&s).unwrap();
+    let f = KicadFormatter::new(KicadVersion::Modern);
+    let options = ser::Options::new().quoting(ser::QuotingPolicy::Kicad).final_newline(true);
+    let mut w = ser::SexpWriter::with_formatter(vec![], f).with_options(options);
+    w.value(&e).unwrap();
+    assert_eq!(s.as_bytes(), &w.finish().unwrap()[..]);
+}
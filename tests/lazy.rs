@@ -0,0 +1,45 @@
+// (c) 2017 Productize SPRL <joost@productize.be>
+
+extern crate symbolic_expressions;
+
+use symbolic_expressions::lazy;
+use symbolic_expressions::parser;
+
+const PCB: &str = "(kicad_pcb (version 4) (host pcbnew \"(2015-05-31 BZR 5692)-product\")
+  (general (links 0) (no_connects 0))
+  (layers (0 F.Cu signal) (31 B.Cu signal))
+  (module A (layer F.Cu)) (module \"B C\" (layer B.Cu)))";
+
+#[test]
+fn test_lazy_find() {
+    let l = lazy::parse_str(PCB).unwrap();
+    assert_eq!(l.name(), Some("kicad_pcb"));
+    assert_eq!(l.len(), 7);
+    let general = l.find("general").unwrap();
+    assert!(!general.is_parsed());
+    assert_eq!(general.sexp().unwrap().list_name().unwrap(), "general");
+    assert!(general.is_parsed());
+    assert!(!l.find("layers").unwrap().is_parsed());
+    assert_eq!(l.find_all("module").count(), 2);
+    assert_eq!(
+        l.find("host").unwrap().text(),
+        "(host pcbnew \"(2015-05-31 BZR 5692)-product\")"
+    );
+}
+
+#[test]
+fn test_lazy_same_as_parse() {
+    let l = lazy::parse_str(PCB).unwrap();
+    let _ = l.get(2).unwrap().sexp().unwrap();
+    assert_eq!(l.into_sexp().unwrap(), parser::parse_str(PCB).unwrap());
+}
+
+#[test]
+fn test_lazy_error_position() {
+    match lazy::parse_str("(a\n  (b \"c)") {
+        Err(symbolic_expressions::SexpError::Parse(e)) => {
+            assert_eq!((e.line(), e.col()), (2, 9));
+        }
+        _ => panic!("expected a parse error"),
+    }
+}
@@ -0,0 +1,83 @@
+// (c) 2017 Productize SPRL <joost@productize.be>
+
+extern crate symbolic_expressions;
+
+use symbolic_expressions::ser::{self, Options};
+use symbolic_expressions::sourcemap::SourceMap;
+use symbolic_expressions::{parser, Adapter, CompactFormatter, PrettyFormatter, Rules,
+                           RulesFormatter, Sexp};
+
+const MODULE: &str = "(module A (layer F.Cu) (pad 1 smd (at 1 2) (size 0.5 0.5)) \
+                      (fp_text reference \"R 1\"))";
+
+#[test]
+fn test_source_map_rules() {
+    let e = parser::parse_str(MODULE).unwrap();
+    let mut rules = Rules::new();
+    rules.insert("pad", 1);
+    rules.insert("fp_text", 1);
+    let f = Adapter::new(RulesFormatter::new(rules));
+    let (s, map) = ser::to_string_with_source_map(&e, f, &Options::new()).unwrap();
+    assert_eq!(
+        "(module A (layer F.Cu)
+  (pad 1 smd (at 1 2) (size 0.5 0.5))
+  (fp_text reference \"R 1\"))",
+        s
+    );
+    let root = map.get(&[]).unwrap();
+    assert_eq!((0, s.len()), (root.start, root.end));
+    let at = map.get(&[3, 3]).unwrap();
+    assert_eq!("(at 1 2)", &s[at.start..at.end]);
+    assert_eq!((1, 13), (at.line, at.col));
+    let r = map.get(&[4, 2]).unwrap();
+    assert_eq!("\"R 1\"", &s[r.start..r.end]);
+    assert_eq!((2, 21, 2, 26), (r.line, r.col, r.end_line, r.end_col));
+    assert!(map.get(&[5]).is_none());
+}
+
+#[test]
+fn test_source_map_pretty() {
+    let e = parser::parse_str(MODULE).unwrap();
+    let f = Adapter::new(PrettyFormatter::new(30));
+    let mut out = vec![];
+    let map = ser::to_writer_with_source_map(&mut out, f, &Options::new(), &e).unwrap();
+    let s = String::from_utf8(out).unwrap();
+    for span in map.spans() {
+        let text = &s[span.start..span.end];
+        let node = span.path.iter().fold(&e, |n, &i| &n.list().unwrap()[i]);
+        assert_eq!(parser::parse_str(text).unwrap(), *node);
+    }
+    let size = map.get(&[3, 4]).unwrap();
+    assert_eq!(size.path, map.at(size.start).unwrap().path);
+    assert_eq!(vec![3, 4, 0], map.at(size.start + 2).unwrap().path);
+    assert_eq!(vec![3, 4, 1], map.at(size.start + 6).unwrap().path);
+}
+
+#[test]
+fn test_source_map_empty() {
+    assert!(SourceMap::build("").unwrap().spans().is_empty());
+    assert!(SourceMap::build("(a (b)").is_err());
+}
+
+#[test]
+fn test_source_map_skips_empty() {
+    let e = Sexp::List(vec!["a".into(), Sexp::Empty, "b".into()]);
+    let f = Adapter::new(CompactFormatter);
+    let (s, map) = ser::to_string_with_source_map(&e, f, &Options::new()).unwrap();
+    assert_eq!("(a  b)", s);
+    assert!(map.get(&[1]).is_none());
+    let b = map.get(&[2]).unwrap();
+    assert_eq!("b", &s[b.start..b.end]);
+}
+
+#[test]
+fn test_source_map_inner_quote() {
+    let e = Sexp::List(vec!["a".into(), "b".into(), "c\" d".into(), "e".into()]);
+    let f = Adapter::new(CompactFormatter);
+    let (s, map) = ser::to_string_with_source_map(&e, f, &Options::new()).unwrap();
+    let c = map.get(&[2]).unwrap();
+    assert_eq!("\"c\\\" d\"", &s[c.start..c.end]);
+    let e = map.get(&[3]).unwrap();
+    assert_eq!("e", &s[e.start..e.end]);
+    assert_eq!(4, map.spans().len() - 1);
+}
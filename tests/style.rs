@@ -0,0 +1,139 @@
+// (c) 2017 Productize SPRL <joost@productize.be>
+
+extern crate symbolic_expressions;
+
+use std::env;
+use std::fs;
+
+use symbolic_expressions::float::FloatFormat;
+use symbolic_expressions::ser::{self, QuotingPolicy};
+use symbolic_expressions::style::Style;
+use symbolic_expressions::{parser, Indent, LineEnding};
+
+const STYLE: &str = "(format_style (version 1)
+  (width 40)
+  (quoting kicad)
+  (floats (trimmed 3))
+  (rule fp_line (newline) (absolute_indent 1))
+  (rule (module pad) (newline) (children_on_own_line) (close_on_own_line)))";
+
+#[test]
+fn test_style_parse() {
+    let style = Style::parse_str(STYLE).unwrap();
+    assert_eq!(Some(40), style.width);
+    assert_eq!(QuotingPolicy::Kicad, style.quoting);
+    assert_eq!(Some(FloatFormat::Trimmed(3)), style.floats);
+    let path = ["module".to_string(), "pad".to_string()];
+    let rule = style.rules.get(&path).unwrap();
+    assert!(rule.close_on_own_line);
+    assert_eq!(Indent::Relative(1), rule.indent);
+    assert!(style.rules.get(&["pad".to_string()]).is_none());
+}
+
+#[test]
+fn test_style_serialize() {
+    let style = Style::parse_str(STYLE).unwrap();
+    let e = parser::parse_str(
+        "(module R1 (layer F.Cu) \
+         (fp_line (start 0.10000 -1) (end 1 1) (layer F.SilkS) (width 0.12)) \
+         (pad 1 smd rect (at 0.5 0) (size 1 1)))",
+    ).unwrap();
+    let s = ser::to_string_with_style(&e, &style).unwrap();
+    assert_eq!(
+        "(module R1 (layer \"F.Cu\")
+  (fp_line (start 0.1 -1) (end 1 1)
+    (layer \"F.SilkS\") (width 0.12))
+  (pad \"1\" smd rect
+    (at 0.5 0)
+    (size 1 1)
+  ))",
+        s
+    );
+}
+
+#[test]
+fn test_style_save_load() {
+    let mut style = Style::parse_str(STYLE).unwrap();
+    style.line_ending = LineEnding::CrLf;
+    style.final_newline = true;
+    let name = env::temp_dir().join("symbolic_expressions_test_style.sexp");
+    let name = name.to_str().unwrap();
+    style.save(name).unwrap();
+    assert_eq!(style, Style::load(name).unwrap());
+    fs::remove_file(name).unwrap();
+}
+
+#[test]
+fn test_style_errors() {
+    assert!(Style::parse_str("(format_style (colour red))").is_err());
+    assert!(Style::parse_str("(format_style (version 2))").is_err());
+    assert!(Style::parse_str("(format_style (quoting sometimes))").is_err());
+    assert!(Style::parse_str("(format_style (line_ending cr))").is_err());
+    assert!(Style::parse_str("(format_style (rule a (sideways)))").is_err());
+    // sizes can't be negative
+    assert!(Style::parse_str("(format_style (indent (spaces -1)))").is_err());
+    assert!(Style::parse_str("(format_style (width -5))").is_err());
+    assert!(Style::parse_str("(format_style (floats (fixed -2)))").is_err());
+    assert!(Style::parse_str("(format_style (rule a (indent -1)))").is_err());
+}
+
+fn check_infer(name: &str) -> Style {
+    let s = fs::read_to_string(name).unwrap();
+    let style = Style::infer(&s).unwrap();
+    let e = parser::parse_str(&s).unwrap();
+    let out = ser::to_string_with_style(&e, &style).unwrap();
+    assert_eq!(s, out);
+    style
+}
+
+#[test]
+fn test_style_infer_legacy() {
+    let style = check_infer("tests/data/R_0603_1608Metric_legacy.kicad_mod");
+    assert_eq!(Some("  ".to_string()), style.indent);
+    assert_eq!(QuotingPolicy::KicadLegacy, style.quoting);
+}
+
+#[test]
+fn test_style_infer_crlf() {
+    let s = fs::read_to_string("tests/data/R_0603_1608Metric_legacy.kicad_mod").unwrap();
+    let s = s.replace('\n', "\r\n");
+    let style = Style::infer(&s).unwrap();
+    assert_eq!(LineEnding::CrLf, style.line_ending);
+    assert!(style.final_newline);
+    let e = parser::parse_str(&s).unwrap();
+    assert_eq!(s, ser::to_string_with_style(&e, &style).unwrap());
+}
+
+#[test]
+fn test_style_infer_custom() {
+    let name = env::temp_dir().join("symbolic_expressions_test_infer.sexp");
+    let name = name.to_str().unwrap();
+    fs::write(
+        name,
+        "(config
+    (server \"a b\"
+        (port 80)
+        (paths /a /b /c
+            /d /e /f
+            /g)
+    )
+    (server c
+        (port 8080)
+    ))",
+    ).unwrap();
+    let style = check_infer(name);
+    assert_eq!(Some("    ".to_string()), style.indent);
+    assert_eq!(Some(3), style.rules.get(&["paths".to_string()]).unwrap().max_items_per_line);
+    fs::remove_file(name).unwrap();
+}
+
+#[test]
+fn test_style_infer_modern() {
+    let s = fs::read_to_string("tests/data/R_0603_1608Metric_modern.kicad_mod").unwrap();
+    let style = Style::infer(&s).unwrap();
+    assert_eq!(Some("\t".to_string()), style.indent);
+    assert_eq!(QuotingPolicy::Kicad, style.quoting);
+    let effects = style.rules.get(&["effects".to_string()]).unwrap();
+    assert!(effects.newline_before && effects.close_on_own_line);
+    assert_eq!(Indent::Relative(1), effects.indent);
+}